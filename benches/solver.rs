@@ -0,0 +1,64 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nalgebra::Vector3;
+
+use tomala_space_program::presets::Preset;
+use tomala_space_program::solver::{self, Physics, State};
+
+/// `n` equal masses spread over a sphere of radius 10, moving slowly.
+fn cluster(n: usize) -> (State, Vec<f64>) {
+    let mut s = State { x: Vec::new(), v: Vec::new(), t: 0.0 };
+    for i in 0..n {
+        // Golden angle spiral, with the radius growing with the index.
+        let f = (i as f64 + 0.5) / n as f64;
+        let (sin, cos) = (i as f64 * 2.399963229728653).sin_cos();
+        let y = 1.0 - 2.0 * f;
+        let r = (1.0 - y * y).sqrt();
+        let x = Vector3::new(r * cos, y, r * sin) * (10.0 * f.cbrt());
+        s.x.push(x);
+        s.v.push(Vector3::new(-x.z, 0.0, x.x) * 0.1);
+    }
+    (s, vec!(1.0; n))
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("State::step");
+    for p in Preset::default_presets().iter().take(3) {
+        let (s, m) = (p.state(), p.masses());
+        group.bench_function(BenchmarkId::new("preset", &p.name), |b| {
+            let mut s = s.clone();
+            b.iter(|| s.step(0.001, &m, &p.physics))
+        });
+    }
+    for &n in [10, 100].iter() {
+        let (s, m) = cluster(n);
+        let physics = Physics { g: 1.0, softening: 0.1 };
+        group.bench_function(BenchmarkId::new("cluster", n), |b| {
+            let mut s = s.clone();
+            b.iter(|| s.step(0.001, &m, &physics))
+        });
+    }
+    group.finish();
+}
+
+fn acceleration(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_acceleration");
+    let physics = Physics { g: 1.0, softening: 0.1 };
+    for &n in [3, 30, 300].iter() {
+        let (s, m) = cluster(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &s, |b, s| {
+            b.iter(|| solver::get_acceleration(s, &m, &physics))
+        });
+    }
+    // The same system with all but three bodies massless.
+    let (s, mut m) = cluster(300);
+    for mass in m.iter_mut().skip(3) {
+        *mass = 0.0;
+    }
+    group.bench_with_input(BenchmarkId::new("massless", 300), &s, |b, s| {
+        b.iter(|| solver::get_acceleration(s, &m, &physics))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, step, acceleration);
+criterion_main!(benches);
@@ -0,0 +1,214 @@
+use crate::solver::State;
+use crate::collision::Collision;
+
+/// Size of the initial perturbation of the shadow, relative to the size of
+/// the state in phase space.
+const SEPARATION: f64 = 1e-8;
+
+/// A copy of `s` with one coordinate of one body nudged: components 0 to 2
+/// are the position and 3 to 5 the velocity. The nudge is `relative` times
+/// the largest distance from the origin, or the largest speed, of any body.
+pub fn twin(s: &State, body: usize, component: usize, relative: f64) -> State {
+    let mut twin = s.clone();
+    if component < 3 {
+        let scale = s.x.iter().map(|x| x.norm()).fold(0.0, f64::max);
+        twin.x[body][component] += relative * scale;
+    } else {
+        let scale = s.v.iter().map(|v| v.norm()).fold(0.0, f64::max);
+        twin.v[body][component - 3] += relative * scale;
+    }
+    twin
+}
+
+/// Sensitivity to initial conditions, measured by following a shadow copy
+/// of the state that starts a tiny distance away.
+///
+/// The caller advances the shadow with the same steps as the state and
+/// then calls `renormalize`, which measures how much the two have drifted
+/// apart and pulls the shadow back to the initial distance along the same
+/// direction. Distances are taken over all positions and velocities
+/// together, which doesn't change the exponent in the long run.
+#[derive(Clone)]
+pub struct Chaos {
+    shadow: State,
+    separation: f64,
+    /// Time of the last renormalization.
+    t: f64,
+    /// Simulated time the measurement has covered.
+    pub elapsed: f64,
+    /// Sum of the logarithmic growths of the separation.
+    growth: f64,
+    /// Integral of time times the growth rate, from which MEGNO follows.
+    weighted_growth: f64,
+    /// Integral of MEGNO over time, for its mean.
+    megno_integral: f64
+}
+
+/// Distance between two states in phase space, over the positions and
+/// velocities of the bodies in `b`.
+pub fn phase_distance(a: &State, b: &State) -> f64 {
+    let mut sum = 0.0;
+    for i in 0..b.len() {
+        sum += (a.x[i] - b.x[i]).norm_squared() + (a.v[i] - b.v[i]).norm_squared();
+    }
+    sum.sqrt()
+}
+
+/// Starts `chaos` over from `s` if any of `events` merged two bodies: the
+/// shadow still has both of them and no longer matches the state.
+pub fn restart_after_merges(chaos: &mut Option<Chaos>, s: &State, events: &[Collision]) {
+    if chaos.is_some() && events.iter().any(|e| e.merged) {
+        *chaos = Some(Chaos::new(s));
+    }
+}
+
+impl Chaos {
+
+    /// Starts measuring from `s`. The shadow is displaced in a fixed
+    /// direction that mixes all coordinates, so that it doesn't happen to
+    /// be a symmetry such as a translation of the whole system.
+    pub fn new(s: &State) -> Chaos {
+        let mut size = 0.0;
+        for i in 0..s.len() {
+            size += s.x[i].norm_squared() + s.v[i].norm_squared();
+        }
+        let separation = SEPARATION * size.sqrt().max(1e-300);
+        let mut shadow = s.clone();
+        let mut direction = Vec::new();
+        for i in 0..2 * s.len() {
+            for c in 0..3 {
+                // Multiples of the golden angle don't repeat.
+                direction.push(((3 * i + c + 1) as f64 * 2.399963229728653).sin());
+            }
+        }
+        let norm = direction.iter().map(|d| d * d).sum::<f64>().sqrt();
+        for i in 0..s.len() {
+            for c in 0..3 {
+                shadow.x[i][c] += separation * direction[6 * i + c] / norm;
+                shadow.v[i][c] += separation * direction[6 * i + 3 + c] / norm;
+            }
+        }
+        Chaos { shadow, separation, t: s.t, elapsed: 0.0, growth: 0.0, weighted_growth: 0.0, megno_integral: 0.0 }
+    }
+
+    /// The perturbed copy, to be advanced like the state.
+    pub fn shadow(&mut self) -> &mut State {
+        &mut self.shadow
+    }
+
+    /// Measures the separation from `s`, which must be at the same time as
+    /// the shadow, and rescales it back to the initial size.
+    pub fn renormalize(&mut self, s: &State) {
+        let dt = (s.t - self.t).abs();
+        let d = phase_distance(s, &self.shadow);
+        if dt == 0.0 || d == 0.0 || !d.is_finite() {
+            return;
+        }
+        self.t = s.t;
+        let growth = (d / self.separation).ln();
+        let start = self.elapsed;
+        self.elapsed += dt;
+        self.growth += growth;
+        // The growth rate is taken as constant over the interval.
+        self.weighted_growth += growth * (start + self.elapsed) / 2.0;
+        self.megno_integral += self.megno() * dt;
+        let scale = self.separation / d;
+        for i in 0..self.shadow.len() {
+            self.shadow.x[i] = s.x[i] + (self.shadow.x[i] - s.x[i]) * scale;
+            self.shadow.v[i] = s.v[i] + (self.shadow.v[i] - s.v[i]) * scale;
+        }
+    }
+
+    /// Estimate of the maximal Lyapunov exponent, in inverse time units. It
+    /// tends to zero as 1/t for regular motion.
+    pub fn lyapunov(&self) -> f64 {
+        if self.elapsed == 0.0 { 0.0 } else { self.growth / self.elapsed }
+    }
+
+    /// The MEGNO indicator Y, twice the time-weighted average growth rate of
+    /// the separation.
+    pub fn megno(&self) -> f64 {
+        if self.elapsed == 0.0 { 0.0 } else { 2.0 * self.weighted_growth / self.elapsed }
+    }
+
+    /// Time average of MEGNO, which tends to 2 for quasi-periodic motion,
+    /// to 0 for stable periodic motion and grows as λt/2 for chaotic motion.
+    pub fn mean_megno(&self) -> f64 {
+        if self.elapsed == 0.0 { 0.0 } else { self.megno_integral / self.elapsed }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+    use crate::solver::{Integrator, Physics, RungeKutta4, Yoshida4};
+    use crate::collision::{Collisions, CollisionPolicy};
+    use na::Vector3;
+
+    fn measure(name: &str, time: f64, h: f64) -> Chaos {
+        let p = Preset::default_presets().into_iter().find(|p| p.name == name).unwrap();
+        let (mut s, m) = (p.state(), p.masses());
+        let mut chaos = Chaos::new(&s);
+        let steps = (time / h).round() as usize;
+        for k in 0..steps {
+            Yoshida4.step(&mut s, h, &m, &p.physics);
+            Yoshida4.step(chaos.shadow(), h, &m, &p.physics);
+            if k % 10 == 9 {
+                chaos.renormalize(&s);
+            }
+        }
+        chaos
+    }
+
+    #[test]
+    fn twin_is_nudged_by_the_relative_size() {
+        let p = &Preset::default_presets()[0];
+        let s = p.state();
+        let speed = s.v.iter().map(|v| v.norm()).fold(0.0, f64::max);
+        let t = twin(&s, 2, 4, 1e-6);
+        assert!((phase_distance(&s, &t) / (1e-6 * speed) - 1.0).abs() < 1e-6);
+        assert_eq!(t.x[2], s.x[2]);
+    }
+
+    #[test]
+    fn measurement_restarts_when_bodies_merge() {
+        let mut s = State {
+            x: vec!(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 10.0)),
+            v: vec!(Vector3::new(1.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.3, 0.0, 0.0)),
+            t: 0.0
+        };
+        let (mut m, mut radii) = (vec!(1.0, 1.0, 1.0), vec!(0.2, 0.2, 0.2));
+        let p = Physics::default();
+        let mut collisions = Collisions::new(CollisionPolicy::Merge);
+        let mut chaos = Some(Chaos::new(&s));
+        let mut merged = false;
+        for _ in 0..2000 {
+            RungeKutta4.step(&mut s, 0.001, &m, &p);
+            if let Some(ref mut c) = chaos {
+                RungeKutta4.step(c.shadow(), 0.001, &m, &p);
+            }
+            let events = collisions.handle(&mut s, &mut m, &mut radii);
+            merged |= events.iter().any(|e| e.merged);
+            restart_after_merges(&mut chaos, &s, &events);
+            chaos.as_mut().unwrap().renormalize(&s);
+        }
+        let chaos = chaos.unwrap();
+        assert!(merged);
+        assert_eq!(chaos.shadow.len(), 2);
+        assert!(chaos.elapsed > 0.0 && chaos.lyapunov().is_finite());
+    }
+
+    #[test]
+    fn three_stars_are_chaotic_and_the_rest_regular() {
+        let chaotic = measure("Three Stars", 30.0, 0.002);
+        assert!(chaotic.mean_megno() > 10.0, "{}", chaotic.mean_megno());
+        for name in ["Sun-Earth-Moon", "Figure Eight", "L4"].iter() {
+            let regular = measure(name, 30.0, 0.002);
+            assert!((regular.mean_megno() - 2.0).abs() < 0.5, "{}: {}", name, regular.mean_megno());
+            assert!(regular.lyapunov() < chaotic.lyapunov() / 4.0);
+        }
+    }
+
+}
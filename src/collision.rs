@@ -0,0 +1,210 @@
+use na::Vector3;
+
+use crate::solver::State;
+
+/// What happens when the spheres of two bodies overlap.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CollisionPolicy {
+    /// Bodies pass through each other, nothing is reported.
+    Ignore,
+    /// Contacts are reported, the caller is expected to stop the simulation.
+    Stop,
+    /// The bodies merge into one, conserving mass and momentum.
+    Merge,
+    /// The bodies bounce off each other elastically.
+    Bounce
+}
+
+impl CollisionPolicy {
+
+    pub fn all() -> [CollisionPolicy; 4] {
+        [CollisionPolicy::Ignore, CollisionPolicy::Stop, CollisionPolicy::Merge, CollisionPolicy::Bounce]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CollisionPolicy::Ignore => "ignore",
+            CollisionPolicy::Stop => "stop",
+            CollisionPolicy::Merge => "merge",
+            CollisionPolicy::Bounce => "bounce"
+        }
+    }
+
+}
+
+/// A contact between bodies `a` and `b`, with indices valid at the time it
+/// was reported. With `CollisionPolicy::Merge`, `b` has been merged into `a`
+/// and then removed, so every index above `b` (possibly `a` as well) is
+/// shifted down by one afterwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Collision {
+    pub a: usize,
+    pub b: usize,
+    pub position: Vector3<f64>,
+    pub relative_speed: f64,
+    pub merged: bool
+}
+
+pub struct Collisions {
+    pub policy: CollisionPolicy,
+    touching: Vec<(usize, usize)>
+}
+
+impl Collisions {
+
+    pub fn new(policy: CollisionPolicy) -> Collisions {
+        Collisions { policy, touching: Vec::new() }
+    }
+
+    /// Forgets which bodies are in contact, e.g. after the bodies changed.
+    pub fn reset(&mut self) {
+        self.touching.clear();
+    }
+
+    /// Finds overlapping bodies and applies the policy to them. A pair is
+    /// only reported when it comes into contact, not again while it stays
+    /// overlapping.
+    pub fn handle(&mut self, s: &mut State, m: &mut Vec<f64>, radii: &mut Vec<f64>) -> Vec<Collision> {
+        let mut events = Vec::new();
+        if self.policy == CollisionPolicy::Ignore {
+            return events;
+        }
+        if self.policy == CollisionPolicy::Merge {
+            while let Some((a, b)) = overlapping(s, radii).into_iter().next() {
+                events.push(merge(s, m, radii, a, b));
+            }
+            self.touching.clear();
+            return events;
+        }
+        let touching = overlapping(s, radii);
+        for &(a, b) in touching.iter() {
+            if !self.touching.contains(&(a, b)) {
+                events.push(contact(s, a, b));
+            }
+            if self.policy == CollisionPolicy::Bounce {
+                bounce(s, m, a, b);
+            }
+        }
+        self.touching = touching;
+        events
+    }
+
+}
+
+fn overlapping(s: &State, radii: &[f64]) -> Vec<(usize, usize)> {
+    let mut ret = Vec::new();
+    for i in 0..s.len() {
+        for j in (i + 1)..s.len() {
+            if (s.x[j] - s.x[i]).norm() < radii[i] + radii[j] {
+                ret.push((i, j));
+            }
+        }
+    }
+    ret
+}
+
+fn contact(s: &State, a: usize, b: usize) -> Collision {
+    Collision {
+        a,
+        b,
+        position: (s.x[a] + s.x[b]) / 2.0,
+        relative_speed: (s.v[a] - s.v[b]).norm(),
+        merged: false
+    }
+}
+
+/// Mass-weighted average, falling back to the plain average for two
+/// massless bodies.
+fn weights(ma: f64, mb: f64) -> (f64, f64) {
+    if ma + mb == 0.0 {
+        (0.5, 0.5)
+    } else {
+        (ma / (ma + mb), mb / (ma + mb))
+    }
+}
+
+/// Merges the lighter body of the pair into the heavier one. The result sits
+/// at their center of mass with their total momentum and the volume of both
+/// spheres.
+fn merge(s: &mut State, m: &mut Vec<f64>, radii: &mut Vec<f64>, a: usize, b: usize) -> Collision {
+    let (a, b) = if m[b] > m[a] { (b, a) } else { (a, b) };
+    let mut event = contact(s, a, b);
+    let (wa, wb) = weights(m[a], m[b]);
+    s.x[a] = s.x[a] * wa + s.x[b] * wb;
+    s.v[a] = s.v[a] * wa + s.v[b] * wb;
+    m[a] += m[b];
+    radii[a] = (radii[a].powi(3) + radii[b].powi(3)).cbrt();
+    s.remove(b);
+    m.remove(b);
+    radii.remove(b);
+    event.merged = true;
+    event
+}
+
+/// Exchanges momentum along the line between the centers, if the bodies are
+/// approaching each other.
+fn bounce(s: &mut State, m: &[f64], a: usize, b: usize) {
+    // Coincident centers give no direction to bounce along.
+    let n = match (s.x[b] - s.x[a]).try_normalize(0.0) {
+        Some(n) => n,
+        None => return
+    };
+    let approach = (s.v[a] - s.v[b]).dot(&n);
+    if approach <= 0.0 {
+        return;
+    }
+    let (wa, wb) = weights(m[a], m[b]);
+    s.v[a] -= n * (2.0 * wb * approach);
+    s.v[b] += n * (2.0 * wa * approach);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics;
+
+    fn head_on() -> (State, Vec<f64>, Vec<f64>) {
+        let s = State {
+            x: vec![Vector3::new(-0.5, 0.0, 0.0), Vector3::new(0.5, 0.0, 0.0), Vector3::new(10.0, 0.0, 0.0)],
+            v: vec![Vector3::new(1.0, 0.0, 0.0), Vector3::new(-2.0, 1.0, 0.0), Vector3::zeros()],
+            t: 0.0
+        };
+        (s, vec![3.0, 1.0, 1.0], vec![1.0, 0.5, 0.5])
+    }
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let (mut s, mut m, mut r) = head_on();
+        let p = diagnostics::momentum(&s, &m);
+        let events = Collisions::new(CollisionPolicy::Merge).handle(&mut s, &mut m, &mut r);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].merged && events[0].a == 0 && events[0].b == 1);
+        assert_eq!(s.len(), 2);
+        assert_eq!(m, vec![4.0, 1.0]);
+        assert!((diagnostics::momentum(&s, &m) - p).norm() < 1e-12);
+        assert!((r[0] - 1.125f64.cbrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bounce_conserves_energy_and_reports_once() {
+        let (mut s, mut m, mut r) = head_on();
+        let p = diagnostics::momentum(&s, &m);
+        let k = diagnostics::kinetic_energy(&s, &m);
+        let mut collisions = Collisions::new(CollisionPolicy::Bounce);
+        assert_eq!(collisions.handle(&mut s, &mut m, &mut r).len(), 1);
+        assert!(s.v[0].x < 0.0 && s.v[1].x > 0.0);
+        assert!((diagnostics::momentum(&s, &m) - p).norm() < 1e-12);
+        assert!((diagnostics::kinetic_energy(&s, &m) - k).abs() < 1e-12);
+        assert_eq!(collisions.handle(&mut s, &mut m, &mut r).len(), 0);
+    }
+
+    #[test]
+    fn coincident_bodies_do_not_bounce() {
+        let (mut s, mut m, mut r) = head_on();
+        s.x[1] = s.x[0];
+        let v = s.v.clone();
+        assert_eq!(Collisions::new(CollisionPolicy::Bounce).handle(&mut s, &mut m, &mut r).len(), 1);
+        assert_eq!(s.v, v);
+    }
+
+}
@@ -0,0 +1,115 @@
+use std::time::Instant;
+
+use crate::solver::{State, Physics, Integrator, DormandPrince};
+use crate::diagnostics::{self, Diagnostics};
+
+/// Tolerance of the Dormand-Prince run that the fixed step runs are
+/// compared against.
+const REFERENCE_TOLERANCE: f64 = 1e-13;
+/// Only errors in this range are used to fit the order: smaller ones are
+/// dominated by round-off and the error of the reference, larger ones by
+/// steps too long for the error to follow a power law.
+const FIT_RANGE: (f64, f64) = (1e-10, 1e-2);
+
+/// The outcome of integrating a preset with one integrator and step size.
+#[derive(Clone, Copy, Debug)]
+pub struct Run {
+    pub integrator: &'static str,
+    pub steps: usize,
+    pub h: f64,
+    /// Relative change of the total energy at the end of the run.
+    pub energy_drift: f64,
+    /// Largest distance of a body from where the reference run puts it,
+    /// relative to the size of the system.
+    pub position_error: f64,
+    /// Wall-clock time of the run.
+    pub seconds: f64
+}
+
+/// Characteristic time of a system: `sqrt(R^3 / GM)` for its total mass
+/// and the largest distance of a body from the center of mass. An orbit
+/// at that distance around the whole mass takes 2π of these.
+pub fn dynamical_time(s: &State, m: &[f64], p: &Physics) -> f64 {
+    let size = system_size(s, m);
+    let total: f64 = m.iter().sum();
+    (size * size * size / (p.g * total)).sqrt()
+}
+
+fn system_size(s: &State, m: &[f64]) -> f64 {
+    let com = diagnostics::center_of_mass(s, m);
+    s.x.iter().map(|x| (x - com).norm()).fold(0.0, f64::max)
+}
+
+/// The state after `span`, integrated with tight tolerances.
+pub fn reference(s: &State, m: &[f64], p: &Physics, span: f64) -> State {
+    let mut s = s.clone();
+    DormandPrince::new(REFERENCE_TOLERANCE, REFERENCE_TOLERANCE).advance(&mut s, span, m, p);
+    s
+}
+
+/// Integrates `s` over `span` in `steps` equal steps and compares the result
+/// with `reference`, the same state integrated accurately.
+pub fn run(integrator: &dyn Integrator, s: &State, m: &[f64], p: &Physics, span: f64, steps: usize, reference: &State) -> Run {
+    let h = span / steps as f64;
+    let mut end = s.clone();
+    let start = Instant::now();
+    for _ in 0..steps {
+        integrator.step(&mut end, h, m, p);
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    let size = system_size(s, m);
+    let error = (0..s.len()).map(|i| (end.x[i] - reference.x[i]).norm()).fold(0.0, f64::max);
+    Run {
+        integrator: integrator.name(),
+        steps,
+        h,
+        energy_drift: Diagnostics::new(&end, m, p).drift(&Diagnostics::new(s, m, p)).energy,
+        position_error: error / size,
+        seconds
+    }
+}
+
+/// Observed order of convergence: the slope of the position error against
+/// the step size on a log-log scale, fitted by least squares to the runs
+/// whose error is in `FIT_RANGE`. `None` if fewer than two qualify.
+pub fn fit_order(runs: &[Run]) -> Option<f64> {
+    let points: Vec<(f64, f64)> = runs.iter()
+        .filter(|r| r.position_error > FIT_RANGE.0 && r.position_error < FIT_RANGE.1)
+        .map(|r| (r.h.ln(), r.position_error.ln()))
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mx = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let my = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxy: f64 = points.iter().map(|p| (p.0 - mx) * (p.1 - my)).sum();
+    let sxx: f64 = points.iter().map(|p| (p.0 - mx) * (p.0 - mx)).sum();
+    if sxx == 0.0 { None } else { Some(sxy / sxx) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+    use crate::solver::{Euler, SemiImplicitEuler, RungeKutta4, Leapfrog, Yoshida4};
+
+    #[test]
+    fn observed_orders_match_the_methods() {
+        let p = Preset::default_presets().into_iter().find(|p| p.name == "Figure Eight").unwrap();
+        let (s, m) = (p.state(), p.masses());
+        let span = dynamical_time(&s, &m, &p.physics);
+        let reference = reference(&s, &m, &p.physics, span);
+        let integrators: [(&dyn Integrator, f64); 5] = [
+            (&Euler, 1.0), (&SemiImplicitEuler, 1.0), (&Leapfrog, 2.0), (&RungeKutta4, 4.0), (&Yoshida4, 4.0)
+        ];
+        for &(integrator, order) in integrators.iter() {
+            let runs: Vec<Run> = [50, 100, 200, 400].iter()
+                .map(|&steps| run(integrator, &s, &m, &p.physics, span, steps, &reference))
+                .collect();
+            let fitted = fit_order(&runs).unwrap();
+            assert!((fitted - order).abs() < 0.3, "{}: order {}", integrator.name(), fitted);
+        }
+    }
+
+}
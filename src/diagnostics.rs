@@ -0,0 +1,225 @@
+use na::Vector3;
+
+use crate::solver::{State, Physics};
+
+/// Total kinetic energy.
+#[allow(clippy::needless_range_loop)]
+pub fn kinetic_energy(s: &State, m: &[f64]) -> f64 {
+    let mut energy = 0.0;
+    for i in 0..s.len() {
+        let v = s.v[i].norm();
+        energy += m[i] * v * v / 2.0;
+    }
+    energy
+}
+
+/// Total gravitational potential energy, with the same softening as the
+/// forces in the solver.
+pub fn potential_energy(s: &State, m: &[f64], p: &Physics) -> f64 {
+    let eps2 = p.softening * p.softening;
+    let mut energy = 0.0;
+    for i in 0..s.len() {
+        for j in (i + 1)..s.len() {
+            let r = ((s.x[i] - s.x[j]).norm_squared() + eps2).sqrt();
+            energy -= p.g * m[i] * m[j] / r;
+        }
+    }
+    energy
+}
+
+/// Total energy, kinetic plus gravitational potential.
+pub fn energy(s: &State, m: &[f64], p: &Physics) -> f64 {
+    kinetic_energy(s, m) + potential_energy(s, m, p)
+}
+
+/// Total linear momentum.
+#[allow(clippy::needless_range_loop)]
+pub fn momentum(s: &State, m: &[f64]) -> Vector3<f64> {
+    let mut momentum = Vector3::zeros();
+    for i in 0..s.len() {
+        momentum += s.v[i] * m[i];
+    }
+    momentum
+}
+
+/// Total angular momentum about the origin.
+#[allow(clippy::needless_range_loop)]
+pub fn angular_momentum(s: &State, m: &[f64]) -> Vector3<f64> {
+    let mut momentum = Vector3::zeros();
+    for i in 0..s.len() {
+        momentum += s.x[i].cross(&s.v[i]) * m[i];
+    }
+    momentum
+}
+
+/// Mass-weighted mean position. Zero if there is no mass at all.
+#[allow(clippy::needless_range_loop)]
+pub fn center_of_mass(s: &State, m: &[f64]) -> Vector3<f64> {
+    let total: f64 = m.iter().sum();
+    if total == 0.0 {
+        return Vector3::zeros();
+    }
+    let mut com = Vector3::zeros();
+    for i in 0..s.len() {
+        com += s.x[i] * m[i];
+    }
+    com / total
+}
+
+/// Velocity of the center of mass. Zero if there is no mass at all.
+pub fn center_of_mass_velocity(s: &State, m: &[f64]) -> Vector3<f64> {
+    let total: f64 = m.iter().sum();
+    if total == 0.0 {
+        return Vector3::zeros();
+    }
+    momentum(s, m) / total
+}
+
+/// The virial ratio 2T / |U|, which averages to 1 over time for a bound
+/// system in equilibrium.
+pub fn virial_ratio(s: &State, m: &[f64], p: &Physics) -> f64 {
+    2.0 * kinetic_energy(s, m) / potential_energy(s, m, p).abs()
+}
+
+/// All conserved quantities of a state, computed at once.
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostics {
+    pub kinetic_energy: f64,
+    pub potential_energy: f64,
+    pub energy: f64,
+    pub momentum: Vector3<f64>,
+    pub angular_momentum: Vector3<f64>,
+    pub center_of_mass: Vector3<f64>,
+    pub center_of_mass_velocity: Vector3<f64>,
+    pub virial_ratio: f64
+}
+
+/// Change of the conserved quantities relative to a reference state.
+///
+/// Each value is the size of the change divided by the size of the
+/// reference value, or the absolute change where the reference is zero
+/// (e.g. the momentum of a system at rest).
+#[derive(Clone, Copy, Debug)]
+pub struct Drift {
+    pub energy: f64,
+    pub momentum: f64,
+    pub angular_momentum: f64
+}
+
+impl Diagnostics {
+
+    pub fn new(s: &State, m: &[f64], p: &Physics) -> Diagnostics {
+        let kinetic_energy = kinetic_energy(s, m);
+        let potential_energy = potential_energy(s, m, p);
+        Diagnostics {
+            kinetic_energy,
+            potential_energy,
+            energy: kinetic_energy + potential_energy,
+            momentum: momentum(s, m),
+            angular_momentum: angular_momentum(s, m),
+            center_of_mass: center_of_mass(s, m),
+            center_of_mass_velocity: center_of_mass_velocity(s, m),
+            virial_ratio: 2.0 * kinetic_energy / potential_energy.abs()
+        }
+    }
+
+    pub fn drift(&self, reference: &Diagnostics) -> Drift {
+        fn relative(change: f64, reference: f64) -> f64 {
+            if reference == 0.0 {
+                change
+            } else {
+                change / reference
+            }
+        }
+        Drift {
+            energy: relative((self.energy - reference.energy).abs(), reference.energy.abs()),
+            momentum: relative((self.momentum - reference.momentum).norm(), reference.momentum.norm()),
+            angular_momentum: relative(
+                (self.angular_momentum - reference.angular_momentum).norm(),
+                reference.angular_momentum.norm()
+            )
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+    use crate::units::UnitSystem;
+
+    fn preset(name: &str) -> Preset {
+        Preset::default_presets().into_iter().find(|p| p.name == name).unwrap()
+    }
+
+    #[test]
+    fn energy_is_kinetic_plus_potential() {
+        let p = preset("Sun-Earth-Moon");
+        let (s, m, ph) = (p.state(), p.masses(), p.physics);
+        let kinetic = 16.0 * 7.07 * 7.07 / 2.0 + 0.1 * (4.0 * 4.0 + 7.07 * 7.07) / 2.0;
+        let potential = -1000.0 * 16.0 / 20.0 - 1000.0 * 0.1 / 401.0f64.sqrt() - 16.0 * 0.1 / 1.0;
+        assert!((kinetic_energy(&s, &m) - kinetic).abs() < 1e-9);
+        assert!((potential_energy(&s, &m, &ph) - potential).abs() < 1e-9);
+        assert!((energy(&s, &m, &ph) - kinetic - potential).abs() < 1e-9);
+    }
+
+    #[test]
+    fn potential_scales_with_g_and_softening() {
+        let p = preset("Figure Eight");
+        let (s, m) = (p.state(), p.masses());
+        let base = potential_energy(&s, &m, &Physics::default());
+        let doubled = potential_energy(&s, &m, &Physics { g: 2.0, softening: 0.0 });
+        let softened = potential_energy(&s, &m, &Physics { g: 1.0, softening: 1.0 });
+        assert!((doubled - 2.0 * base).abs() < 1e-9);
+        assert!(softened > base);
+    }
+
+    #[test]
+    fn figure_eight_is_centered_and_at_rest() {
+        let p = preset("Figure Eight");
+        let d = Diagnostics::new(&p.state(), &p.masses(), &p.physics);
+        assert!(d.momentum.norm() < 1e-9);
+        assert!(d.center_of_mass.norm() < 1e-12);
+        assert!(d.center_of_mass_velocity.norm() < 1e-12);
+    }
+
+    #[test]
+    fn rotating_triangle_is_in_virial_equilibrium() {
+        let p = preset("Three Stars");
+        let d = Diagnostics::new(&p.state(), &p.masses(), &p.physics);
+        assert!((d.virial_ratio - 1.0).abs() < 1e-12);
+        assert!(d.momentum.norm() < 1e-9);
+    }
+
+    #[test]
+    fn angular_momentum_of_l4() {
+        let p = preset("L4");
+        let (s, m) = (p.state(), p.masses());
+        let mut expected = Vector3::zeros();
+        for b in p.bodies.iter() {
+            expected += b.x.cross(&b.v) * b.mass;
+        }
+        assert!((angular_momentum(&s, &m) - expected).norm() < 1e-9);
+        assert!(angular_momentum(&s, &m).y < 0.0);
+    }
+
+    #[test]
+    fn drift_stays_small_under_rk4() {
+        // The step and tolerances only make sense in simulation units.
+        for p in Preset::default_presets().into_iter().filter(|p| p.units == UnitSystem::Simulation) {
+            let (mut s, m) = (p.state(), p.masses());
+            let reference = Diagnostics::new(&s, &m, &p.physics);
+            let zero = reference.drift(&reference);
+            assert_eq!((zero.energy, zero.momentum, zero.angular_momentum), (0.0, 0.0, 0.0));
+            for _ in 0..1000 {
+                s.step(0.0001, &m, &p.physics);
+            }
+            let drift = Diagnostics::new(&s, &m, &p.physics).drift(&reference);
+            assert!(drift.energy < 1e-9, "{}: energy drift {}", p.name, drift.energy);
+            assert!(drift.momentum < 1e-9, "{}: momentum drift {}", p.name, drift.momentum);
+            assert!(drift.angular_momentum < 1e-9, "{}: angular momentum drift {}", p.name, drift.angular_momentum);
+        }
+    }
+
+}
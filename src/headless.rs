@@ -0,0 +1,458 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use tomala_space_program::solver::{self, State, Integrator, DormandPrince};
+use tomala_space_program::presets::Preset;
+use tomala_space_program::scenario;
+use tomala_space_program::diagnostics::Diagnostics;
+use tomala_space_program::collision::{Collisions, CollisionPolicy};
+use tomala_space_program::units::UnitSystem;
+use tomala_space_program::chaos::{self, Chaos};
+use tomala_space_program::convergence::{self, Run};
+use tomala_space_program::periodic;
+
+const USAGE: &str = "\
+usage: tomala-space-program run [options]
+
+options:
+  --preset NAME        preset to simulate (default: first preset)
+  --scenario FILE      simulate a scenario file instead of a preset
+  --integrator NAME    integrator name, or \"adaptive\" for Dormand-Prince (default: RK4)
+  --step H             integration step size, which must divide the interval
+                       (default: 0.0001)
+  --tol TOL            tolerance for the adaptive integrator (default: 1e-9)
+  --g G                override the gravitational constant of the preset
+  --softening EPS      override the softening length of the preset
+  --collisions POLICY  ignore, stop, merge or bounce (default: ignore)
+  --units UNITS        convert the preset to SI or astronomical units; times,
+                       steps and the other options are in the preset's units
+  --time T             total simulated time (default: 10)
+  --interval DT        time between output rows; the last row is at --time
+                       even if the interval doesn't divide it (default: 0.01)
+  --chaos              add the Lyapunov exponent and MEGNO columns, measured
+                       with a perturbed shadow copy of the state
+  --output FILE        write CSV to FILE instead of stdout
+  --list               list available presets and integrators";
+
+const CONVERGENCE_USAGE: &str = "\
+usage: tomala-space-program convergence [options]
+
+Integrates each preset with every fixed step integrator and a range of step
+sizes, and compares the results with a tight Dormand-Prince run.
+
+options:
+  --preset NAME        preset to measure, may be repeated (default: all)
+  --integrator NAME    integrator to measure, may be repeated (default: all)
+  --orbits N           length of the runs in orbits, 2π dynamical times
+                       each (default: 1)
+  --steps LIST         comma separated numbers of steps per run
+                       (default: 250,500,1000,2000,4000,8000)";
+
+const PERIODIC_USAGE: &str = "\
+usage: tomala-space-program periodic [options]
+
+Refines the initial conditions of known periodic three-body orbits with the
+shooting method until each closes on itself after one period.
+
+options:
+  --orbit NAME         orbit to refine, may be repeated (default: all)
+  --tol TOL            tolerance of the integration over a period
+                       (default: 1e-13)
+  --save               save each refined orbit as a scenario in scenarios/
+  --list               list the known orbits";
+
+struct Options {
+    preset: Option<String>,
+    scenario: Option<String>,
+    integrator: String,
+    step: f64,
+    tol: f64,
+    g: Option<f64>,
+    softening: Option<f64>,
+    collisions: CollisionPolicy,
+    units: Option<UnitSystem>,
+    time: f64,
+    interval: f64,
+    output: Option<String>,
+    chaos: bool,
+    list: bool
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        preset: None,
+        scenario: None,
+        integrator: "RK4".to_string(),
+        step: 0.0001,
+        tol: 1e-9,
+        g: None,
+        softening: None,
+        collisions: CollisionPolicy::Ignore,
+        units: None,
+        time: 10.0,
+        interval: 0.01,
+        output: None,
+        chaos: false,
+        list: false
+    };
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--preset" => opts.preset = Some(value()?.clone()),
+            "--scenario" => opts.scenario = Some(value()?.clone()),
+            "--integrator" => opts.integrator = value()?.clone(),
+            "--step" => opts.step = parse_number(arg, value()?)?,
+            "--tol" => opts.tol = parse_number(arg, value()?)?,
+            "--g" => opts.g = Some(parse_number(arg, value()?)?),
+            "--softening" => opts.softening = Some(parse_number(arg, value()?)?),
+            "--collisions" => {
+                let name = value()?;
+                opts.collisions = *CollisionPolicy::all().iter().find(|p| name_matches(p.name(), name))
+                    .ok_or_else(|| format!("unknown collision policy: {}", name))?;
+            },
+            "--units" => {
+                let name = value()?;
+                opts.units = Some(*UnitSystem::all().iter().find(|u| name_matches(u.name(), name))
+                    .ok_or_else(|| format!("unknown units: {}", name))?);
+            },
+            "--time" => opts.time = parse_number(arg, value()?)?,
+            "--interval" => opts.interval = parse_number(arg, value()?)?,
+            "--output" => opts.output = Some(value()?.clone()),
+            "--chaos" => opts.chaos = true,
+            "--list" => opts.list = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("unknown argument: {}\n\n{}", arg, USAGE))
+        }
+    }
+    if opts.step <= 0.0 || opts.interval <= 0.0 || opts.time < 0.0 {
+        return Err("step and interval must be positive and time non-negative".to_string());
+    }
+    if opts.preset.is_some() && opts.scenario.is_some() {
+        return Err("--preset and --scenario can't be used together".to_string());
+    }
+    Ok(opts)
+}
+
+fn parse_number(arg: &str, value: &str) -> Result<f64, String> {
+    value.parse().map_err(|_| format!("invalid number for {}: {}", arg, value))
+}
+
+/// Compares names ignoring case, spaces and punctuation, so that
+/// `figure-eight` matches "Figure Eight".
+fn name_matches(name: &str, query: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect()
+    };
+    normalize(name) == normalize(query)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let opts = parse_args(args)?;
+    let mut presets = scenario::all_presets();
+    let integrators = solver::integrators();
+
+    if opts.list {
+        println!("presets:");
+        for p in presets.iter() {
+            println!("  {} ({} units)", p.name, p.units.name());
+        }
+        println!("integrators:");
+        for i in integrators.iter() {
+            println!("  {}", i.name());
+        }
+        println!("  adaptive");
+        return Ok(());
+    }
+
+    if let Some(ref path) = opts.scenario {
+        presets.insert(0, scenario::load(Path::new(path))?);
+    }
+    let preset = match opts.preset {
+        Some(ref name) => presets.iter().find(|p| name_matches(&p.name, name))
+            .ok_or_else(|| format!("unknown preset: {}", name))?,
+        _ => &presets[0]
+    };
+    let converted;
+    let preset = match opts.units {
+        Some(units) => {
+            converted = preset.convert(units)?;
+            &converted
+        },
+        None => preset
+    };
+    let integrator: Option<&Box<dyn Integrator>> = if name_matches("adaptive", &opts.integrator) {
+        None
+    } else {
+        Some(integrators.iter().find(|i| name_matches(i.name(), &opts.integrator))
+            .ok_or_else(|| format!("unknown integrator: {}", opts.integrator))?)
+    };
+
+    let out: Box<dyn Write> = match opts.output {
+        Some(ref path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout())
+    };
+    let mut out = BufWriter::new(out);
+
+    let mut state = preset.state();
+    let mut masses = preset.masses();
+    let mut radii: Vec<f64> = preset.bodies.iter().map(|b| b.radius as f64).collect();
+    let mut collisions = Collisions::new(opts.collisions);
+    // Original index of every body still in the simulation, so that the CSV
+    // columns stay put when bodies merge.
+    let mut alive: Vec<usize> = (0..state.len()).collect();
+    let mut physics = preset.physics;
+    physics.g = opts.g.unwrap_or(physics.g);
+    physics.softening = opts.softening.unwrap_or(physics.softening);
+    // The last interval is cut short to end exactly at `time`.
+    let rows = (opts.time / opts.interval * (1.0 - 1e-12)).ceil() as usize;
+    let substeps = opts.interval / opts.step;
+    if integrator.is_some() && (substeps < 1.0 - 1e-9 || (substeps - substeps.round()).abs() > 1e-9 * substeps) {
+        return Err(format!("--step {} must divide --interval {} evenly", opts.step, opts.interval));
+    }
+    let substeps = substeps.round() as usize;
+    let mut adaptive = DormandPrince::new(opts.tol, opts.tol);
+    let mut shadow_adaptive = DormandPrince::new(opts.tol, opts.tol);
+    let mut chaos = if opts.chaos { Some(Chaos::new(&state)) } else { None };
+
+    write_header(&mut out, preset, opts.chaos).map_err(|e| e.to_string())?;
+    let reference = Diagnostics::new(&state, &masses, &physics);
+    let columns = preset.bodies.len();
+    write_row(&mut out, &state, &reference, &reference, chaos.as_ref(), &alive, columns).map_err(|e| e.to_string())?;
+    let mut stopped = false;
+    for row in 0..rows {
+        let span = opts.interval.min(opts.time - row as f64 * opts.interval);
+        // The adaptive integrator covers a whole interval in one call, and
+        // the steps of a shortened interval are shortened evenly.
+        let steps = match integrator {
+            Some(_) if span < opts.interval => ((span / opts.step).ceil() as usize).max(1),
+            Some(_) => substeps,
+            None => 1
+        };
+        let h = span / steps as f64;
+        for _ in 0..steps {
+            match integrator {
+                Some(integrator) => integrator.step(&mut state, h, &masses, &physics),
+                None => {
+                    adaptive.advance(&mut state, span, &masses, &physics);
+                }
+            }
+            if let Some(ref mut chaos) = chaos {
+                match integrator {
+                    Some(integrator) => integrator.step(chaos.shadow(), h, &masses, &physics),
+                    None => {
+                        shadow_adaptive.advance(chaos.shadow(), span, &masses, &physics);
+                    }
+                }
+                chaos.renormalize(&state);
+            }
+            let events = collisions.handle(&mut state, &mut masses, &mut radii);
+            for e in events.iter() {
+                eprintln!("t = {}: {} hit {} at v = {}{}", state.t, preset.bodies[alive[e.a]].name,
+                          preset.bodies[alive[e.b]].name, e.relative_speed, if e.merged { ", merged" } else { "" });
+                if e.merged {
+                    alive.remove(e.b);
+                }
+                stopped |= opts.collisions == CollisionPolicy::Stop;
+            }
+            chaos::restart_after_merges(&mut chaos, &state, &events);
+            if stopped {
+                break;
+            }
+        }
+        if !stopped {
+            // Drops the round-off of the summed steps.
+            state.t = opts.time.min((row + 1) as f64 * opts.interval);
+        }
+        let d = Diagnostics::new(&state, &masses, &physics);
+        write_row(&mut out, &state, &d, &reference, chaos.as_ref(), &alive, columns)
+            .map_err(|e| e.to_string())?;
+        if stopped {
+            break;
+        }
+    }
+    out.flush().map_err(|e| e.to_string())
+}
+
+fn write_header(out: &mut dyn Write, preset: &Preset, chaos: bool) -> io::Result<()> {
+    write!(out, "t")?;
+    for body in preset.bodies.iter() {
+        for q in ["x", "y", "z", "vx", "vy", "vz"].iter() {
+            write!(out, ",{}_{}", body.name, q)?;
+        }
+    }
+    write!(out, ",energy,energy_drift,px,py,pz,lx,ly,lz")?;
+    if chaos {
+        write!(out, ",lyapunov,megno,mean_megno")?;
+    }
+    writeln!(out)
+}
+
+fn write_row(
+    out: &mut dyn Write,
+    s: &State,
+    d: &Diagnostics,
+    reference: &Diagnostics,
+    chaos: Option<&Chaos>,
+    alive: &[usize],
+    columns: usize
+) -> io::Result<()> {
+    write!(out, "{}", s.t)?;
+    for c in 0..columns {
+        match alive.iter().position(|&a| a == c) {
+            Some(i) => write!(out, ",{},{},{},{},{},{}", s.x[i].x, s.x[i].y, s.x[i].z, s.v[i].x, s.v[i].y, s.v[i].z)?,
+            // Merged into another body.
+            None => write!(out, ",NaN,NaN,NaN,NaN,NaN,NaN")?
+        }
+    }
+    let (p, l) = (d.momentum, d.angular_momentum);
+    write!(out, ",{},{},{},{},{},{},{},{}", d.energy, d.drift(reference).energy, p.x, p.y, p.z, l.x, l.y, l.z)?;
+    if let Some(c) = chaos {
+        write!(out, ",{},{},{}", c.lyapunov(), c.megno(), c.mean_megno())?;
+    }
+    writeln!(out)
+}
+
+/// Prints a table of the energy drift, position error and wall-clock time
+/// of every run, and the order of convergence fitted for each integrator.
+pub fn convergence(args: &[String]) -> Result<(), String> {
+    let mut preset_names = Vec::new();
+    let mut integrator_names = Vec::new();
+    let mut orbits = 1.0;
+    let mut steps = vec!(250, 500, 1000, 2000, 4000, 8000);
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--preset" => preset_names.push(value()?.clone()),
+            "--integrator" => integrator_names.push(value()?.clone()),
+            "--orbits" => orbits = parse_number(arg, value()?)?,
+            "--steps" => {
+                let list = value()?;
+                steps = list.split(',')
+                    .map(|n| n.trim().parse().ok().filter(|&n: &usize| n > 0))
+                    .collect::<Option<Vec<usize>>>()
+                    .ok_or_else(|| format!("invalid list of steps: {}", list))?;
+            },
+            "-h" | "--help" => return Err(CONVERGENCE_USAGE.to_string()),
+            _ => return Err(format!("unknown argument: {}\n\n{}", arg, CONVERGENCE_USAGE))
+        }
+    }
+    if orbits <= 0.0 {
+        return Err("orbits must be positive".to_string());
+    }
+
+    let presets = scenario::all_presets();
+    let presets: Vec<&Preset> = if preset_names.is_empty() {
+        presets.iter().collect()
+    } else {
+        preset_names.iter()
+            .map(|name| presets.iter().find(|p| name_matches(&p.name, name)).ok_or_else(|| format!("unknown preset: {}", name)))
+            .collect::<Result<_, _>>()?
+    };
+    let all = solver::integrators();
+    let integrators: Vec<&Box<dyn Integrator>> = if integrator_names.is_empty() {
+        all.iter().collect()
+    } else {
+        integrator_names.iter()
+            .map(|name| all.iter().find(|i| name_matches(i.name(), name)).ok_or_else(|| format!("unknown integrator: {}", name)))
+            .collect::<Result<_, _>>()?
+    };
+
+    for preset in presets {
+        let (state, masses) = (preset.state(), preset.masses());
+        let physics = preset.physics;
+        let span = orbits * 2.0 * std::f64::consts::PI * convergence::dynamical_time(&state, &masses, &physics);
+        let reference = convergence::reference(&state, &masses, &physics, span);
+        println!("{}: {} over {}", preset.name, if orbits == 1.0 { "1 orbit".to_string() } else { format!("{} orbits", orbits) },
+                 preset.units.format_time(span));
+        println!("{:<20} {:>6} {:>10} {:>13} {:>15} {:>10}", "integrator", "steps", "h", "energy drift", "position error", "time [ms]");
+        for integrator in integrators.iter() {
+            let runs: Vec<Run> = steps.iter()
+                .map(|&n| convergence::run(integrator.as_ref(), &state, &masses, &physics, span, n, &reference))
+                .collect();
+            for r in runs.iter() {
+                println!("{:<20} {:>6} {:>10.3e} {:>13.3e} {:>15.3e} {:>10.3}",
+                         r.integrator, r.steps, r.h, r.energy_drift, r.position_error, r.seconds * 1000.0);
+            }
+            match convergence::fit_order(&runs) {
+                Some(order) => println!("{:<20} observed order {:.2}", "", order),
+                None => println!("{:<20} too few runs in the asymptotic range to fit an order", "")
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Refines the known periodic orbits and optionally saves them as scenarios,
+/// in the style of the Figure Eight preset.
+pub fn periodic(args: &[String]) -> Result<(), String> {
+    let mut names = Vec::new();
+    let mut tol = 1e-13;
+    let mut save = false;
+    let mut list = false;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--orbit" => names.push(value()?.clone()),
+            "--tol" => tol = parse_number(arg, value()?)?,
+            "--save" => save = true,
+            "--list" => list = true,
+            "-h" | "--help" => return Err(PERIODIC_USAGE.to_string()),
+            _ => return Err(format!("unknown argument: {}\n\n{}", arg, PERIODIC_USAGE))
+        }
+    }
+
+    let known = periodic::known_orbits();
+    if list {
+        for orbit in known.iter() {
+            println!("{} (period about {})", orbit.name, orbit.period);
+        }
+        return Ok(());
+    }
+    let orbits: Vec<&periodic::KnownOrbit> = if names.is_empty() {
+        known.iter().collect()
+    } else {
+        names.iter()
+            .map(|name| known.iter().find(|o| name_matches(o.name, name)).ok_or_else(|| format!("unknown orbit: {}", name)))
+            .collect::<Result<_, _>>()?
+    };
+
+    let eight = Preset::default_presets().into_iter().find(|p| p.name == "Figure Eight")
+        .ok_or_else(|| "the Figure Eight preset is missing".to_string())?;
+    let masses = eight.masses();
+    // The lengths of the Figure Eight preset are ten times those of the
+    // published orbits, with a thousand times the mass.
+    let scale = 10.0;
+    for orbit in orbits {
+        let guess = orbit.state(scale);
+        let before = periodic::closure(&guess, &masses, &eight.physics, orbit.period, tol);
+        let refined = match periodic::refine(&guess, &masses, &eight.physics, orbit.period, tol) {
+            Ok(refined) => refined,
+            Err(e) => {
+                eprintln!("{}: {}", orbit.name, e);
+                continue;
+            }
+        };
+        println!("{}: period {:.10}, closure error {:.2e} -> {:.2e} after {} iterations",
+                 orbit.name, refined.period, before, refined.closure, refined.iterations);
+        if save {
+            // Keeps the built-in Figure Eight apart from the refined one.
+            let name = if Preset::default_presets().iter().any(|p| p.name == orbit.name) {
+                format!("Refined {}", orbit.name)
+            } else {
+                orbit.name.to_string()
+            };
+            let preset = Preset::from_state(name.clone(), &eight.bodies, &refined.state, &masses, eight.units, eight.physics);
+            let dir = Path::new(scenario::SCENARIO_DIR);
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            let path = dir.join(format!("{}.toml", scenario::file_stem(&name)));
+            scenario::save(&preset, &path)?;
+            println!("  saved as {}", path.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::collections::vec_deque::VecDeque;
+
+use crate::solver::State;
+use crate::presets::BodyData;
+
+/// Everything needed to resume the simulation from an earlier moment. The
+/// time is `state.t`.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub state: State,
+    pub masses: Vec<f64>,
+    /// The bodies present at that moment, which differ from the current
+    /// ones once bodies have merged.
+    pub bodies: Vec<BodyData>
+}
+
+/// Ring buffer of the most recent snapshots, oldest first.
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize
+}
+
+impl History {
+
+    pub fn new(capacity: usize) -> History {
+        History { snapshots: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Snapshot> {
+        self.snapshots.get(i)
+    }
+
+    pub fn last(&self) -> Option<&Snapshot> {
+        self.snapshots.back()
+    }
+
+    /// Appends a snapshot, dropping the oldest one when full. A snapshot
+    /// taken at the same time as the last one replaces it, so that changes
+    /// made while paused are kept.
+    pub fn record(&mut self, snapshot: Snapshot) {
+        if self.last().is_some_and(|last| last.state.t == snapshot.state.t) {
+            self.snapshots.pop_back();
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Keeps only the first `len` snapshots, forgetting the future of a
+    /// moment the simulation is resumed from.
+    pub fn truncate(&mut self, len: usize) {
+        self.snapshots.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+
+    fn snapshot(p: &Preset, t: f64) -> Snapshot {
+        let mut state = p.state();
+        state.t = t;
+        Snapshot { state, masses: p.masses(), bodies: p.bodies.clone() }
+    }
+
+    #[test]
+    fn keeps_the_most_recent_snapshots() {
+        let p = &Preset::default_presets()[0];
+        let mut history = History::new(3);
+        for t in 0..5 {
+            history.record(snapshot(p, t as f64));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get(0).unwrap().state.t, 2.0);
+        assert_eq!(history.last().unwrap().state.t, 4.0);
+        history.record(snapshot(p, 4.0));
+        assert_eq!(history.len(), 3);
+        history.truncate(1);
+        assert_eq!(history.last().unwrap().state.t, 2.0);
+    }
+
+}
@@ -0,0 +1,199 @@
+use na::{Vector2, Vector3, Matrix2};
+
+use crate::solver::{State, Physics};
+
+/// The five Lagrange points of a pair of bodies, as if the pair moved on a
+/// circular orbit with its current separation.
+///
+/// The points are found numerically in the plane of the pair's orbit,
+/// taking the softening of gravity into account: L1 between the bodies, L2
+/// beyond the secondary, L3 beyond the primary, and L4 and L5 leading and
+/// trailing the secondary.
+#[derive(Clone, Copy, Debug)]
+pub struct LagrangePoints {
+    pub points: [Vector3<f64>; 5],
+    pub barycenter: Vector3<f64>,
+    /// Velocity of the barycenter.
+    pub drift: Vector3<f64>,
+    /// Angular velocity of the frame co-rotating with the pair.
+    pub rotation: Vector3<f64>
+}
+
+/// Bisection stops once the bracket is this small relative to the
+/// separation of the pair.
+const TOLERANCE: f64 = 1e-14;
+
+impl LagrangePoints {
+
+    /// Lagrange points of `secondary` orbiting `primary`, or `None` if one
+    /// of them is massless or they are in the same place.
+    pub fn new(s: &State, m: &[f64], p: &Physics, primary: usize, secondary: usize) -> Option<LagrangePoints> {
+        let (m1, m2) = (m[primary], m[secondary]);
+        let d = s.x[secondary] - s.x[primary];
+        let r = d.norm();
+        if m1 <= 0.0 || m2 <= 0.0 || r == 0.0 || primary == secondary {
+            return None;
+        }
+        let total = m1 + m2;
+        let barycenter = (s.x[primary] * m1 + s.x[secondary] * m2) / total;
+        let drift = (s.v[primary] * m1 + s.v[secondary] * m2) / total;
+        let ex = d / r;
+        // The pair orbits counterclockwise around the normal; a pair moving
+        // straight at each other is taken to orbit in the x-z plane.
+        let normal = d.cross(&(s.v[secondary] - s.v[primary])).try_normalize(0.0)
+            .or_else(|| (Vector3::y() - ex * ex.y).try_normalize(1e-12))
+            .unwrap_or_else(Vector3::z);
+        let ey = normal.cross(&ex);
+
+        // In the co-rotating plane, with the barycenter at the origin and
+        // the secondary on the positive first axis.
+        let eps2 = p.softening * p.softening;
+        let a1 = Vector2::new(-r * m2 / total, 0.0);
+        let a2 = Vector2::new(r * m1 / total, 0.0);
+        let omega2 = p.g * total / (r * r + eps2).powf(1.5);
+        let residual = |u: Vector2<f64>| -> Vector2<f64> {
+            let pull = |a: Vector2<f64>, mass: f64| {
+                let d = a - u;
+                d * (p.g * mass) / (d.norm_squared() + eps2).powf(1.5)
+            };
+            pull(a1, m1) + pull(a2, m2) + u * omega2
+        };
+
+        let axial = |x: f64| residual(Vector2::new(x, 0.0)).x;
+        let delta = r * TOLERANCE;
+        let l1 = bisect(&axial, a1.x + delta, a2.x - delta, delta);
+        let l2 = bisect(&axial, a2.x + delta, a2.x + r, delta);
+        let l3 = bisect(&axial, a1.x - 2.0 * r, a1.x - delta, delta);
+        let l4 = newton(&residual, Vector2::new(a1.x + r / 2.0, r * 3.0f64.sqrt() / 2.0), r);
+        let l5 = newton(&residual, Vector2::new(a1.x + r / 2.0, -r * 3.0f64.sqrt() / 2.0), r);
+
+        let to_space = |u: Vector2<f64>| barycenter + ex * u.x + ey * u.y;
+        Some(LagrangePoints {
+            points: [
+                to_space(Vector2::new(l1, 0.0)),
+                to_space(Vector2::new(l2, 0.0)),
+                to_space(Vector2::new(l3, 0.0)),
+                to_space(l4),
+                to_space(l5)
+            ],
+            barycenter,
+            drift,
+            rotation: normal * omega2.sqrt()
+        })
+    }
+
+    /// Position and velocity of a body resting at point L`k + 1` in the
+    /// co-rotating frame.
+    pub fn state(&self, k: usize) -> (Vector3<f64>, Vector3<f64>) {
+        let x = self.points[k];
+        (x, self.drift + self.rotation.cross(&(x - self.barycenter)))
+    }
+
+}
+
+/// Root of `f` between `a` and `b`, where it changes sign.
+fn bisect<F: Fn(f64) -> f64>(f: &F, mut a: f64, mut b: f64, tolerance: f64) -> f64 {
+    let fa = f(a);
+    while b - a > tolerance {
+        let mid = (a + b) / 2.0;
+        if (f(mid) > 0.0) == (fa > 0.0) {
+            a = mid;
+        } else {
+            b = mid;
+        }
+    }
+    (a + b) / 2.0
+}
+
+/// Zero of `f` near `start`, by Newton's method with a finite difference
+/// Jacobian. `scale` is the size of the problem.
+fn newton<F: Fn(Vector2<f64>) -> Vector2<f64>>(f: &F, start: Vector2<f64>, scale: f64) -> Vector2<f64> {
+    let h = scale * 1e-7;
+    let mut u = start;
+    for _ in 0..50 {
+        let y = f(u);
+        let dx = (f(u + Vector2::new(h, 0.0)) - f(u - Vector2::new(h, 0.0))) / (2.0 * h);
+        let dy = (f(u + Vector2::new(0.0, h)) - f(u - Vector2::new(0.0, h))) / (2.0 * h);
+        let step = match Matrix2::from_columns(&[dx, dy]).try_inverse() {
+            Some(inverse) => inverse * y,
+            None => break
+        };
+        u -= step;
+        if step.norm() < scale * TOLERANCE {
+            break;
+        }
+    }
+    u
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+
+    /// Two bodies on a circular orbit of radius 10 around each other.
+    fn binary(m2: f64) -> (State, Vec<f64>) {
+        let total = 1000.0 + m2;
+        let v = (total / 10.0).sqrt();
+        let s = State {
+            x: vec!(Vector3::new(-10.0 * m2 / total, 0.0, 0.0), Vector3::new(10.0 * 1000.0 / total, 0.0, 0.0)),
+            v: vec!(Vector3::new(0.0, 0.0, v * m2 / total), Vector3::new(0.0, 0.0, -v * 1000.0 / total)),
+            t: 0.0
+        };
+        (s, vec!(1000.0, m2))
+    }
+
+    #[test]
+    fn triangular_and_hill_points() {
+        let (s, m) = binary(1.0);
+        let l = LagrangePoints::new(&s, &m, &Physics::default(), 0, 1).unwrap();
+        for k in 3..5 {
+            assert!(((l.points[k] - s.x[0]).norm() - 10.0).abs() < 1e-9);
+            assert!(((l.points[k] - s.x[1]).norm() - 10.0).abs() < 1e-9);
+        }
+        // L4 leads the secondary, which moves towards -z.
+        assert!(l.points[3].z < 0.0 && l.points[4].z > 0.0);
+        // Close to the Hill radius for a light secondary.
+        let hill = 10.0 * (1.0f64 / 3000.0).cbrt();
+        assert!(((s.x[1] - l.points[0]).norm() / hill - 1.0).abs() < 0.05);
+        assert!(((l.points[1] - s.x[1]).norm() / hill - 1.0).abs() < 0.05);
+        assert!(l.points[2].x < -9.9 && l.points[2].x > -10.1);
+    }
+
+    #[test]
+    fn body_at_l4_stays_there() {
+        let (mut s, mut m) = binary(10.0);
+        let physics = Physics::default();
+        let l = LagrangePoints::new(&s, &m, &physics, 0, 1).unwrap();
+        let (x, v) = l.state(3);
+        s.x.push(x);
+        s.v.push(v);
+        m.push(0.0);
+        for _ in 0..2000 {
+            s.step(0.001, &m, &physics);
+        }
+        let l = LagrangePoints::new(&s, &m, &physics, 0, 1).unwrap();
+        assert!((s.x[2] - l.points[3]).norm() < 1e-6);
+    }
+
+    #[test]
+    fn presets_sit_on_their_points() {
+        let presets = Preset::default_presets();
+        let find = |name| presets.iter().find(|p| p.name == name).unwrap();
+        // The root of the Euler quintic for a mass ratio of 1000.
+        let s = find("L1").state();
+        assert!(((s.x[1] - s.x[2]).norm() - 2.7076404184011786).abs() < 1e-9);
+        // The pair isn't quite circular, so the trojan librates around L4
+        // instead of resting on it, but stays near it for ten orbits.
+        let p = find("L4");
+        let (mut s, m) = (p.state(), p.masses());
+        let mut farthest: f64 = 0.0;
+        for _ in 0..18000 {
+            s.step(0.01, &m, &p.physics);
+            let l = LagrangePoints::new(&s, &m, &p.physics, 0, 1).unwrap();
+            farthest = farthest.max((s.x[2] - l.points[3]).norm());
+        }
+        assert!(farthest < 0.15 * 20.0, "{}", farthest);
+    }
+
+}
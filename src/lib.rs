@@ -0,0 +1,22 @@
+//! N-body gravity simulation: the state and integrators, the built-in
+//! presets, scenario files, conserved-quantity diagnostics, collisions, unit
+//! systems, the history of past states, orbital elements, Lagrange points,
+//! massless test particles, chaos indicators, convergence measurements and
+//! a periodic orbit finder. The kiss3d viewer lives in the binary and is only
+//! built with the `viewer` feature.
+
+extern crate nalgebra as na;
+
+pub mod solver;
+pub mod presets;
+pub mod scenario;
+pub mod diagnostics;
+pub mod collision;
+pub mod units;
+pub mod history;
+pub mod orbit;
+pub mod lagrange;
+pub mod particles;
+pub mod chaos;
+pub mod convergence;
+pub mod periodic;
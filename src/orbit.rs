@@ -0,0 +1,227 @@
+use std::f64::consts::PI;
+
+use na::{Vector3, Rotation3};
+
+use crate::solver::{State, Physics};
+
+/// Osculating Keplerian elements of a two-body orbit.
+///
+/// Angles are in radians and measured in the x-z plane, which the built-in
+/// presets orbit in: x is the reference direction and -y the pole, so the
+/// presets' orbits have zero inclination. Unbound orbits have a negative
+/// semi-major axis and an infinite period and apoapsis.
+#[derive(Clone, Copy, Debug)]
+pub struct Elements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    /// Longitude of the ascending node.
+    pub node: f64,
+    /// Argument of periapsis.
+    pub periapsis_argument: f64,
+    pub true_anomaly: f64,
+    pub period: f64,
+    pub periapsis: f64,
+    pub apoapsis: f64
+}
+
+/// Below this eccentricity or inclination the orbit counts as circular or
+/// equatorial, and the angles that are undefined there are set to zero.
+const EPSILON: f64 = 1e-10;
+
+/// Converts from scene coordinates to the frame the elements are measured
+/// in, where the reference plane is x-y and the pole is z.
+fn to_reference(v: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.x, v.z, -v.y)
+}
+
+fn from_reference(v: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.x, -v.z, v.y)
+}
+
+/// Angle in `[0, 2π)`.
+fn wrap(angle: f64) -> f64 {
+    angle.rem_euclid(2.0 * PI)
+}
+
+impl Elements {
+
+    /// Elements of a body at relative position `r` and velocity `v` around a
+    /// primary, with `mu` the gravitational parameter G (M + m).
+    pub fn new(r: Vector3<f64>, v: Vector3<f64>, mu: f64) -> Elements {
+        let (r, v) = (to_reference(&r), to_reference(&v));
+        let h = r.cross(&v);
+        let node_line = Vector3::new(-h.y, h.x, 0.0);
+        let e_vec = (r * (v.norm_squared() - mu / r.norm()) - v * r.dot(&v)) / mu;
+        let e = e_vec.norm();
+        let energy = v.norm_squared() / 2.0 - mu / r.norm();
+        let a = -mu / (2.0 * energy);
+        let inclination = (h.z / h.norm()).clamp(-1.0, 1.0).acos();
+
+        let equatorial = node_line.norm() < EPSILON * h.norm();
+        let circular = e < EPSILON;
+        let node = if equatorial { 0.0 } else { wrap(node_line.y.atan2(node_line.x)) };
+        // Angles in the orbital plane are measured from the node line, or
+        // from x in an equatorial orbit, in the direction of motion.
+        let reference = if equatorial { Vector3::x() } else { node_line.normalize() };
+        let angle_to = |u: &Vector3<f64>| -> f64 {
+            let y = h.normalize().cross(&reference);
+            wrap(u.dot(&y).atan2(u.dot(&reference)))
+        };
+        let periapsis_argument = if circular { 0.0 } else { angle_to(&e_vec) };
+        let true_anomaly = wrap(angle_to(&r) - periapsis_argument);
+
+        let periapsis = h.norm_squared() / (mu * (1.0 + e));
+        let (period, apoapsis) = if e < 1.0 {
+            (2.0 * PI * (a * a * a / mu).sqrt(), a * (1.0 + e))
+        } else {
+            (f64::INFINITY, f64::INFINITY)
+        };
+        Elements {
+            semi_major_axis: a,
+            eccentricity: e,
+            inclination,
+            node,
+            periapsis_argument,
+            true_anomaly,
+            period,
+            periapsis,
+            apoapsis
+        }
+    }
+
+    /// Relative position and velocity of a body on this orbit, the inverse
+    /// of `Elements::new`. Only the shape and orientation are used; period,
+    /// periapsis and apoapsis are ignored.
+    pub fn to_state(&self, mu: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let e = self.eccentricity;
+        let p = self.semi_major_axis * (1.0 - e * e);
+        let (sin_nu, cos_nu) = self.true_anomaly.sin_cos();
+        let r = p / (1.0 + e * cos_nu);
+        // In the orbital plane, with periapsis along the first axis.
+        let r_plane = Vector3::new(r * cos_nu, r * sin_nu, 0.0);
+        let v_plane = Vector3::new(-sin_nu, e + cos_nu, 0.0) * (mu / p).sqrt();
+        let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), self.node)
+            * Rotation3::from_axis_angle(&Vector3::x_axis(), self.inclination)
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), self.periapsis_argument);
+        (from_reference(&(rotation * r_plane)), from_reference(&(rotation * v_plane)))
+    }
+
+    /// Elements of `body` relative to `primary`.
+    pub fn relative(s: &State, m: &[f64], p: &Physics, body: usize, primary: usize) -> Elements {
+        Elements::new(s.x[body] - s.x[primary], s.v[body] - s.v[primary], p.g * (m[body] + m[primary]))
+    }
+
+}
+
+/// The heavier body that pulls hardest on body `i`, a sensible default
+/// primary for its orbit. `None` if no body is heavier.
+pub fn dominant_body(s: &State, m: &[f64], i: usize) -> Option<usize> {
+    let mut best = None;
+    let mut strongest = 0.0;
+    for j in 0..s.len() {
+        let pull = m[j] / (s.x[j] - s.x[i]).norm_squared();
+        if m[j] > m[i] && pull > strongest {
+            best = Some(j);
+            strongest = pull;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn ellipse_at_periapsis() {
+        // In the reference frame: r along x, v along y, mu = 1.
+        let el = Elements::new(from_reference(&Vector3::new(1.0, 0.0, 0.0)), from_reference(&Vector3::new(0.0, 1.2, 0.0)), 1.0);
+        assert!(close(el.semi_major_axis, 1.0 / 0.56));
+        assert!(close(el.eccentricity, 0.44));
+        assert!(close(el.inclination, 0.0));
+        assert!(close(el.periapsis, 1.0) && close(el.apoapsis, 1.44 / 0.56));
+        assert!(close(el.periapsis_argument, 0.0) && close(el.true_anomaly, 0.0));
+        assert!(close(el.period, 2.0 * PI * (1.0f64 / 0.56).powf(1.5)));
+    }
+
+    #[test]
+    fn inclined_orbit_past_the_node() {
+        // Ascending node on y, periapsis 90° past it, body at periapsis.
+        let r = Vector3::new(-0.5f64.sqrt(), 0.0, 0.5f64.sqrt());
+        let v = Vector3::new(0.0, -1.2, 0.0);
+        let el = Elements::new(from_reference(&r), from_reference(&v), 1.0);
+        assert!(close(el.inclination, PI / 4.0));
+        assert!(close(el.node, PI / 2.0));
+        assert!(close(el.periapsis_argument, PI / 2.0));
+        assert!(close(el.true_anomaly, 0.0));
+        assert!(close(el.eccentricity, 0.44));
+    }
+
+    #[test]
+    fn hyperbolic_orbit_is_unbound() {
+        let el = Elements::new(Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 2.0), 1.0);
+        assert!(el.eccentricity > 1.0 && el.semi_major_axis < 0.0);
+        assert!(el.period.is_infinite() && el.apoapsis.is_infinite());
+        assert!(close(el.periapsis, 1.0));
+    }
+
+    /// Difference of two angles, ignoring whole turns.
+    fn angle_error(a: f64, b: f64) -> f64 {
+        let d = wrap(a - b);
+        d.min(2.0 * PI - d)
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let orbits = [
+            (20.0, 0.0, 0.0, 0.0, 0.0, 1.0),
+            (1.5, 0.3, 0.2, 1.0, 2.0, 3.0),
+            (7.0, 0.9, 2.5, 4.0, 0.5, 6.0),
+            (-3.0, 1.8, 0.7, 0.3, 5.0, 0.4)
+        ];
+        for &(a, e, i, node, w, nu) in orbits.iter() {
+            let el = Elements {
+                semi_major_axis: a,
+                eccentricity: e,
+                inclination: i,
+                node,
+                periapsis_argument: w,
+                true_anomaly: nu,
+                period: 0.0,
+                periapsis: 0.0,
+                apoapsis: 0.0
+            };
+            let (r, v) = el.to_state(3.0);
+            let back = Elements::new(r, v, 3.0);
+            assert!((back.semi_major_axis - a).abs() < 1e-9 * a.abs(), "a: {} vs {}", back.semi_major_axis, a);
+            assert!((back.eccentricity - e).abs() < 1e-9);
+            assert!(angle_error(back.inclination, i) < 1e-9);
+            if e > 0.0 {
+                assert!(angle_error(back.node, node) < 1e-9);
+                assert!(angle_error(back.periapsis_argument, w) < 1e-9);
+                assert!(angle_error(back.true_anomaly, nu) < 1e-9);
+            }
+            let (r2, v2) = back.to_state(3.0);
+            assert!((r2 - r).norm() < 1e-9 * r.norm() && (v2 - v).norm() < 1e-9 * v.norm());
+        }
+    }
+
+    #[test]
+    fn earth_orbits_the_sun_and_luna_the_earth() {
+        let p = &Preset::default_presets()[0];
+        let (s, m) = (p.state(), p.masses());
+        assert_eq!(dominant_body(&s, &m, 0), None);
+        assert_eq!(dominant_body(&s, &m, 1), Some(0));
+        assert_eq!(dominant_body(&s, &m, 2), Some(1));
+        let earth = Elements::relative(&s, &m, &p.physics, 1, 0);
+        assert!(earth.eccentricity < 0.02 && (earth.semi_major_axis - 20.0).abs() < 0.5);
+        assert!(close(earth.inclination, 0.0));
+    }
+
+}
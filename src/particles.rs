@@ -0,0 +1,183 @@
+use std::f64::consts::PI;
+
+use na::Vector3;
+
+use crate::solver::State;
+use crate::lagrange::LagrangePoints;
+
+/// Test particles: massless bodies that move in the field of the real ones
+/// without pulling on them or on each other. They don't collide, but are
+/// absorbed when they fall into a body.
+#[derive(Clone, Default)]
+pub struct Particles {
+    pub x: Vec<Vector3<f64>>,
+    pub v: Vec<Vector3<f64>>
+}
+
+impl Particles {
+
+    pub fn new() -> Particles {
+        Particles::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.x.clear();
+        self.v.clear();
+    }
+
+    /// Runs `advance` on the bodies with the particles appended to them as
+    /// massless bodies, then moves the particles back out. Any integrator
+    /// can be used this way, and the massless ones only cost time linear in
+    /// their number.
+    pub fn advance<R, F: FnOnce(&mut State, &[f64]) -> R>(&mut self, s: &mut State, m: &[f64], advance: F) -> R {
+        if self.is_empty() {
+            return advance(s, m);
+        }
+        let n = s.len();
+        s.x.append(&mut self.x);
+        s.v.append(&mut self.v);
+        let mut masses = m.to_vec();
+        masses.resize(s.len(), 0.0);
+        let ret = advance(s, &masses);
+        self.x = s.x.split_off(n);
+        self.v = s.v.split_off(n);
+        ret
+    }
+
+    /// Removes the particles inside the spheres of the bodies and returns
+    /// how many there were.
+    pub fn absorb(&mut self, s: &State, radii: &[f64]) -> usize {
+        let before = self.len();
+        let mut i = 0;
+        while i < self.x.len() {
+            if (0..s.len()).any(|j| (self.x[i] - s.x[j]).norm() < radii[j]) {
+                self.x.swap_remove(i);
+                self.v.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        before - self.len()
+    }
+
+    /// Adds `count` particles resting in the frame co-rotating with a pair
+    /// of bodies, scattered over a disk of radius `spread` around Lagrange
+    /// point L`k + 1` in the plane of the pair's orbit.
+    pub fn seed_lagrange(&mut self, l: &LagrangePoints, k: usize, count: usize, spread: f64, seed: u64) {
+        let normal = l.rotation.normalize();
+        let mut rng = Rng::new(seed);
+        for _ in 0..count {
+            let x = l.points[k] + rng.in_disk(&normal) * spread;
+            self.x.push(x);
+            self.v.push(l.drift + l.rotation.cross(&(x - l.barycenter)));
+        }
+    }
+
+    /// Adds `count` particles on circular orbits around a body at `center`
+    /// with gravitational parameter `gm`, spread evenly over the area of a
+    /// ring between two radii in the plane with the given normal.
+    pub fn seed_disk(
+        &mut self,
+        center: (Vector3<f64>, Vector3<f64>),
+        gm: f64,
+        normal: &Vector3<f64>,
+        (inner, outer): (f64, f64),
+        count: usize,
+        seed: u64
+    ) {
+        let normal = normal.normalize();
+        let mut rng = Rng::new(seed);
+        for _ in 0..count {
+            let u = rng.in_disk(&normal).normalize();
+            let r = (inner * inner + rng.next() * (outer * outer - inner * inner)).sqrt();
+            let speed = (gm / r).sqrt();
+            self.x.push(center.0 + u * r);
+            self.v.push(center.1 + normal.cross(&u) * speed);
+        }
+    }
+
+}
+
+/// Xorshift generator, enough to scatter particles reproducibly.
+struct Rng(u64);
+
+impl Rng {
+
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in the unit disk perpendicular to `normal`, which is
+    /// normalized.
+    fn in_disk(&mut self, normal: &Vector3<f64>) -> Vector3<f64> {
+        let a = if normal.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+        let e1 = normal.cross(&a).normalize();
+        let e2 = normal.cross(&e1);
+        let r = self.next().sqrt();
+        let (sin, cos) = (2.0 * PI * self.next()).sin_cos();
+        // Avoids the exact center, which has no direction.
+        (e1 * cos + e2 * sin) * r.max(1e-9)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+    use crate::solver::{Integrator, RungeKutta4};
+
+    #[test]
+    #[allow(clippy::needless_range_loop)]
+    fn particles_move_without_pulling() {
+        let p = &Preset::default_presets()[0];
+        let (mut s, m) = (p.state(), p.masses());
+        let mut reference = s.clone();
+        let mut particles = Particles::new();
+        particles.seed_disk((s.x[0], s.v[0]), p.physics.g * m[0], &Vector3::y(), (5.0, 15.0), 1000, 1);
+        let start: Vec<f64> = particles.x.iter().map(|x| (x - s.x[0]).norm()).collect();
+        for _ in 0..100 {
+            particles.advance(&mut s, &m, |s, m| RungeKutta4.step(s, 0.001, m, &p.physics));
+            RungeKutta4.step(&mut reference, 0.001, &m, &p.physics);
+        }
+        assert_eq!(s.len(), 3);
+        assert_eq!(particles.len(), 1000);
+        for i in 0..s.len() {
+            assert_eq!(s.x[i], reference.x[i]);
+        }
+        // Circular orbits keep their distance from the sun.
+        for i in 0..particles.len() {
+            let r = (particles.x[i] - s.x[0]).norm();
+            assert!((r / start[i] - 1.0).abs() < 0.05, "{} -> {}", start[i], r);
+        }
+    }
+
+    #[test]
+    fn absorbed_inside_bodies() {
+        let p = &Preset::default_presets()[0];
+        let s = p.state();
+        let mut particles = Particles::new();
+        particles.x = vec!(s.x[0], s.x[1] + Vector3::new(0.1, 0.0, 0.0), Vector3::new(1000.0, 0.0, 0.0));
+        particles.v = vec!(Vector3::zeros(); 3);
+        let radii: Vec<f64> = p.bodies.iter().map(|b| b.radius as f64).collect();
+        assert_eq!(particles.absorb(&s, &radii), 2);
+        assert_eq!(particles.x, vec!(Vector3::new(1000.0, 0.0, 0.0)));
+    }
+
+}
@@ -0,0 +1,226 @@
+use na::{DMatrix, DVector, Vector3, SVD};
+
+use crate::solver::{self, State, Physics, DormandPrince};
+use crate::convergence;
+
+/// The orbit is closed once the state after one period is this close to
+/// the initial one, relative to the size of the state.
+const TOLERANCE: f64 = 1e-10;
+const MAX_ITERATIONS: usize = 30;
+/// Relative size of the finite differences of the Jacobian.
+const DIFFERENCE: f64 = 1e-7;
+/// Singular values of the Jacobian below this fraction of the largest are
+/// treated as zero. The symmetries of the problem (moving along the orbit,
+/// translating or rotating it) make some of them vanish.
+const SINGULAR: f64 = 1e-9;
+
+/// Initial conditions of an orbit that closes on itself after `period`.
+#[derive(Clone)]
+pub struct PeriodicOrbit {
+    pub state: State,
+    pub period: f64,
+    /// Relative distance between the initial state and the state after one
+    /// period.
+    pub closure: f64,
+    pub iterations: usize
+}
+
+/// Published initial conditions of a planar periodic orbit of three equal
+/// masses with G = 1, given to a few digits only.
+#[derive(Clone, Copy)]
+pub struct KnownOrbit {
+    pub name: &'static str,
+    pub x: [[f64; 2]; 3],
+    pub v: [[f64; 2]; 3],
+    pub period: f64
+}
+
+impl KnownOrbit {
+
+    /// The initial state with lengths multiplied by `scale` and the masses
+    /// by `scale^3`, which leaves the period unchanged. The plane of the
+    /// orbit is the x-z plane of the viewer.
+    pub fn state(&self, scale: f64) -> State {
+        let to_space = |p: [f64; 2]| Vector3::new(p[0], 0.0, p[1]) * scale;
+        State {
+            x: self.x.iter().map(|&x| to_space(x)).collect(),
+            v: self.v.iter().map(|&v| to_space(v)).collect(),
+            t: 0.0
+        }
+    }
+
+}
+
+/// The figure eight of Chenciner and Montgomery and orbits found by Šuvakov
+/// and Dmitrašinović, which start from a collinear configuration with the
+/// outer bodies at rest relative to each other.
+pub fn known_orbits() -> Vec<KnownOrbit> {
+    let collinear = |name, p1: f64, p2: f64, period| KnownOrbit {
+        name,
+        x: [[-1.0, 0.0], [1.0, 0.0], [0.0, 0.0]],
+        v: [[p1, p2], [p1, p2], [-2.0 * p1, -2.0 * p2]],
+        period
+    };
+    vec!(
+        KnownOrbit {
+            name: "Figure Eight",
+            x: [[0.9700, -0.2431], [-0.9700, 0.2431], [0.0, 0.0]],
+            v: [[0.4662, 0.4324], [0.4662, 0.4324], [-0.9324, -0.8647]],
+            period: 6.326
+        },
+        collinear("Butterfly I", 0.306893, 0.125507, 6.2356),
+        collinear("Moth I", 0.464445, 0.396060, 14.8939),
+        collinear("Yin-Yang Ia", 0.513938, 0.304736, 17.3284),
+        collinear("Goggles", 0.083300, 0.127889, 10.4668),
+        collinear("Dragonfly", 0.080584, 0.588836, 21.2710)
+    )
+}
+
+/// Relative distance between `s` and the state `period` later, integrated
+/// with the given tolerance.
+pub fn closure(s: &State, m: &[f64], p: &Physics, period: f64, tolerance: f64) -> f64 {
+    let (scale, speed) = scales(s, m, p);
+    let residual = residual(&pack(s, scale, speed), period, m, p, tolerance, scale, speed);
+    residual.norm() / pack(s, scale, speed).norm()
+}
+
+/// Refines `guess` and `period` with Newton's method until the orbit closes
+/// on itself: the shooting method, with the state after one period found
+/// by the adaptive integrator with the given tolerance. Fixed steps would
+/// need to be tiny for the close encounters of most orbits.
+///
+/// The unknowns are the initial state and the period, with positions and
+/// velocities divided by the size and typical speed of the system. There
+/// are more unknowns than equations, and the step is the smallest one that
+/// solves the linearized problem in the least squares sense, so the orbit
+/// moves as little as possible along its families (e.g. starting from a
+/// different point of the same orbit). Coordinates that are zero for every
+/// body, such as the y axis of an orbit in the x-z plane, are kept at zero.
+pub fn refine(guess: &State, m: &[f64], p: &Physics, period: f64, tolerance: f64) -> Result<PeriodicOrbit, String> {
+    let (scale, speed) = scales(guess, m, p);
+    let size = pack(guess, scale, speed).norm();
+    let mut y = pack(guess, scale, speed);
+    let mut period = period;
+    let mut f = residual(&y, period, m, p, tolerance, scale, speed);
+    let planar: Vec<bool> = (0..3)
+        .map(|c| (0..guess.len()).all(|i| guess.x[i][c] == 0.0 && guess.v[i][c] == 0.0))
+        .collect();
+    for iteration in 0..MAX_ITERATIONS {
+        if f.norm() < TOLERANCE * size {
+            return Ok(PeriodicOrbit { state: unpack(&y, scale, speed), period, closure: f.norm() / size, iterations: iteration });
+        }
+        let n = y.len();
+        let mut jacobian = DMatrix::zeros(n, n + 1);
+        for k in 0..n {
+            if planar[k % 3] {
+                continue;
+            }
+            let mut shifted = y.clone();
+            shifted[k] += DIFFERENCE;
+            let column = (residual(&shifted, period, m, p, tolerance, scale, speed) - &f) / DIFFERENCE;
+            jacobian.set_column(k, &column);
+        }
+        // The end of the orbit moves with its velocity as the period grows.
+        let end = unpack(&(&f + &y), scale, speed);
+        let acceleration = solver::get_acceleration(&end, m, p);
+        for i in 0..end.len() {
+            for c in 0..3 {
+                jacobian[(6 * i + c, n)] = end.v[i][c] / scale;
+                jacobian[(6 * i + 3 + c, n)] = acceleration[i][c] / speed;
+            }
+        }
+        let svd = SVD::new(jacobian, true, true);
+        let largest = svd.singular_values.max();
+        let mut step = svd.solve(&f, largest * SINGULAR)?;
+        for k in 0..n {
+            if planar[k % 3] {
+                step[k] = 0.0;
+            }
+        }
+        // Halves the step until it improves the closure.
+        let mut factor = 1.0;
+        loop {
+            let next_y = &y - step.rows(0, n) * factor;
+            let next_period = period - step[n] * factor;
+            let next_f = residual(&next_y, next_period, m, p, tolerance, scale, speed);
+            if next_f.norm() < f.norm() {
+                y = next_y;
+                period = next_period;
+                f = next_f;
+                break;
+            }
+            factor /= 2.0;
+            if factor < 1e-3 {
+                return Err(format!("no periodic orbit found near the guess, closure error {:.2e}", f.norm() / size));
+            }
+        }
+    }
+    Err(format!("no periodic orbit found in {} iterations, closure error {:.2e}", MAX_ITERATIONS, f.norm() / size))
+}
+
+/// Length and speed that make the positions and velocities of the system
+/// about one.
+fn scales(s: &State, m: &[f64], p: &Physics) -> (f64, f64) {
+    let scale = s.x.iter().map(|x| x.norm()).fold(0.0, f64::max).max(1e-300);
+    (scale, scale / convergence::dynamical_time(s, m, p))
+}
+
+fn pack(s: &State, scale: f64, speed: f64) -> DVector<f64> {
+    let mut y = DVector::zeros(6 * s.len());
+    for i in 0..s.len() {
+        for c in 0..3 {
+            y[6 * i + c] = s.x[i][c] / scale;
+            y[6 * i + 3 + c] = s.v[i][c] / speed;
+        }
+    }
+    y
+}
+
+fn unpack(y: &DVector<f64>, scale: f64, speed: f64) -> State {
+    let n = y.len() / 6;
+    let mut s = State { x: vec!(Vector3::zeros(); n), v: vec!(Vector3::zeros(); n), t: 0.0 };
+    for i in 0..n {
+        for c in 0..3 {
+            s.x[i][c] = y[6 * i + c] * scale;
+            s.v[i][c] = y[6 * i + 3 + c] * speed;
+        }
+    }
+    s
+}
+
+/// The packed state after `period`, minus the initial one.
+fn residual(y: &DVector<f64>, period: f64, m: &[f64], p: &Physics, tolerance: f64, scale: f64, speed: f64) -> DVector<f64> {
+    let mut s = unpack(y, scale, speed);
+    DormandPrince::new(tolerance, tolerance).advance(&mut s, period, m, p);
+    pack(&s, scale, speed) - y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+    use crate::diagnostics;
+
+    #[test]
+    fn figure_eight_closes_from_four_digits() {
+        let preset = Preset::default_presets().into_iter().find(|p| p.name == "Figure Eight").unwrap();
+        let eight = known_orbits()[0];
+        let guess = eight.state(10.0);
+        let m = vec!(1000.0; 3);
+        let p = Physics::default();
+        assert!(closure(&guess, &m, &p, eight.period, 1e-11) > 1e-4);
+        let orbit = refine(&guess, &m, &p, eight.period, 1e-11).unwrap();
+        assert!(orbit.closure < TOLERANCE);
+        // The guess has a slightly different energy, and the period of a
+        // scaled orbit scales as |E|^(-3/2).
+        let invariant = |s: &State, period: f64| period * diagnostics::energy(s, &m, &p).abs().powf(1.5);
+        let expected = invariant(&preset.state(), 6.32591398);
+        assert!((invariant(&orbit.state, orbit.period) / expected - 1.0).abs() < 1e-6);
+        // Close to the hardcoded conditions, up to the error of the guess.
+        for i in 0..3 {
+            assert!((orbit.state.x[i] - preset.bodies[i].x).norm() < 1e-2);
+            assert!((orbit.state.v[i] - preset.bodies[i].v).norm() < 1e-2);
+        }
+    }
+
+}
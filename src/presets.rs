@@ -1,20 +1,72 @@
 use na::{Vector3, Point3};
-use crate::solver::State;
+use serde::{Serialize, Deserialize};
+use crate::solver::{State, Physics};
+use crate::units::UnitSystem;
+use crate::orbit::Elements;
+use crate::lagrange::LagrangePoints;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Preset {
-    pub name: &'static str,
-    pub bodies: [BodyData; 3]
+    pub name: String,
+    /// Units of the masses, positions and velocities, as well as of `physics`.
+    #[serde(default)]
+    pub units: UnitSystem,
+    #[serde(default)]
+    pub physics: Physics,
+    pub bodies: Vec<BodyData>
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BodyData {
-    pub name: &'static str,
-    pub texture: &'static str,
+    pub name: String,
+    pub texture: String,
     pub color: [f32; 3],
     pub trail_color: Point3<f32>,
     pub radius: f32,
     pub mass: f64,
+    #[serde(default = "Vector3::zeros")]
     pub x: Vector3<f64>,
-    pub v: Vector3<f64>
+    #[serde(default = "Vector3::zeros")]
+    pub v: Vector3<f64>,
+    /// Places the body on an orbit instead, see `Preset::place_orbits`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orbit: Option<OrbitData>
+}
+
+/// Orbital elements of a body around a primary named in the same preset.
+/// Angles are in degrees.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OrbitData {
+    pub primary: String,
+    pub semi_major_axis: f64,
+    #[serde(default)]
+    pub eccentricity: f64,
+    #[serde(default)]
+    pub inclination: f64,
+    #[serde(default)]
+    pub node: f64,
+    #[serde(default)]
+    pub periapsis_argument: f64,
+    #[serde(default)]
+    pub true_anomaly: f64
+}
+
+impl OrbitData {
+
+    pub fn elements(&self) -> Elements {
+        Elements {
+            semi_major_axis: self.semi_major_axis,
+            eccentricity: self.eccentricity,
+            inclination: self.inclination.to_radians(),
+            node: self.node.to_radians(),
+            periapsis_argument: self.periapsis_argument.to_radians(),
+            true_anomaly: self.true_anomaly.to_radians(),
+            period: 0.0,
+            periapsis: 0.0,
+            apoapsis: 0.0
+        }
+    }
+
 }
 
 impl Preset {
@@ -25,243 +77,431 @@ impl Preset {
             three_stars(),
             figure_eight(),
             lagrange_1(),
-            lagrange_4()
+            lagrange_4(),
+            sun_earth_moon_au(),
+            earth_moon_si()
         )
     }
 
-    pub fn masses(&self) -> [f64; 3] {
-        let mut ret = [0.0; 3];
-        for i in 0..3 {
-            ret[i] = self.bodies[i].mass;
-        }
-        ret
+    pub fn masses(&self) -> Vec<f64> {
+        self.bodies.iter().map(|b| b.mass).collect()
     }
 
-    pub fn x(&self) -> [Vector3<f64>; 3] {
-        let mut ret = [Vector3::zeros(); 3];
-        for i in 0..3 {
-            ret[i] = self.bodies[i].x;
+    pub fn x(&self) -> Vec<Vector3<f64>> {
+        self.bodies.iter().map(|b| b.x).collect()
+    }
+
+    pub fn v(&self) -> Vec<Vector3<f64>> {
+        self.bodies.iter().map(|b| b.v).collect()
+    }
+
+    pub fn state(&self) -> State {
+        State { x: self.x(), v: self.v(), t: 0.0 }
+    }
+
+    /// Creates a preset from the given bodies, taking their masses and
+    /// initial state from a running simulation.
+    pub fn from_state(
+        name: String,
+        bodies: &[BodyData],
+        state: &State,
+        masses: &[f64],
+        units: UnitSystem,
+        physics: Physics
+    ) -> Preset {
+        let mut bodies = bodies.to_vec();
+        for (i, body) in bodies.iter_mut().enumerate() {
+            body.mass = masses[i];
+            body.x = state.x[i];
+            body.v = state.v[i];
+            body.orbit = None;
         }
-        ret
+        Preset { name, units, physics, bodies }
     }
 
-    pub fn v(&self) -> [Vector3<f64>; 3] {
-        let mut ret = [Vector3::zeros(); 3];
-        for i in 0..3 {
-            ret[i] = self.bodies[i].v;
+    /// Sets the position and velocity of every body given by its orbit,
+    /// relative to the primary's. Primaries must come before the bodies
+    /// orbiting them.
+    pub fn place_orbits(&mut self) -> Result<(), String> {
+        for i in 0..self.bodies.len() {
+            let orbit = match self.bodies[i].orbit {
+                Some(ref orbit) => orbit.clone(),
+                None => continue
+            };
+            let primary = self.bodies[..i].iter().position(|b| b.name == orbit.primary)
+                .ok_or_else(|| format!("{}: primary {} must be an earlier body", self.bodies[i].name, orbit.primary))?;
+            let mu = self.physics.g * (self.bodies[i].mass + self.bodies[primary].mass);
+            let (x, v) = orbit.elements().to_state(mu);
+            self.bodies[i].x = self.bodies[primary].x + x;
+            self.bodies[i].v = self.bodies[primary].v + v;
         }
-        ret
+        Ok(())
     }
 
-    pub fn state(&self) -> State {
-        State { x: self.x(), v: self.v() }
+    /// The same preset expressed in other units.
+    pub fn convert(&self, to: UnitSystem) -> Result<Preset, String> {
+        let (l, m, t) = self.units.conversion(to)?;
+        let mut preset = self.clone();
+        preset.units = to;
+        preset.physics.g *= l * l * l / (m * t * t);
+        preset.physics.softening *= l;
+        for body in preset.bodies.iter_mut() {
+            body.mass *= m;
+            body.x *= l;
+            body.v *= l / t;
+            body.radius *= l as f32;
+            if let Some(ref mut orbit) = body.orbit {
+                orbit.semi_major_axis *= l;
+            }
+        }
+        Ok(preset)
     }
 
 }
 
 fn sun_earth_moon() -> Preset {
+    let physics = Physics::default();
     Preset {
-        name: "Sun-Earth-Moon",
-        bodies: [
+        name: "Sun-Earth-Moon".to_string(),
+        units: UnitSystem::Simulation,
+        physics,
+        bodies: vec!(
             BodyData {
-                name: "Sol",
-                texture: "sun",
+                name: "Sol".to_string(),
+                texture: "sun".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.92, 0.80, 0.49),
                 radius: 2.0,
                 mass: 1000.0,
                 x: Vector3::new(0.0, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, 0.0)
+                v: Vector3::new(0.0, 0.0, 0.0),
+                orbit: None
             },
             BodyData {
-                name: "Earth",
-                texture: "earth",
+                name: "Earth".to_string(),
+                texture: "earth".to_string(),
                 color: [1.0, 1.0, 1.0],
                 trail_color: Point3::new(0.49, 0.72, 0.92),
                 radius: 0.5,
                 mass: 16.0,
                 x: Vector3::new(20.0, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, 7.07)
+                v: Vector3::new(0.0, 0.0, 7.07),
+                orbit: None
             },
             BodyData {
-                name: "Luna",
-                texture: "moon",
+                name: "Luna".to_string(),
+                texture: "moon".to_string(),
                 color: [1.0, 1.0, 1.0],
                 trail_color: Point3::new(0.94, 0.94, 0.94),
                 radius: 0.25,
                 mass: 0.1,
                 x: Vector3::new(20.0, 0.0, 1.0),
-                v: Vector3::new(0.0, 4.0, 7.07)
+                v: Vector3::new(0.0, 4.0, 7.07),
+                orbit: None
             }
-        ]
+        )
     }
 }
 
 fn three_stars() -> Preset {
+    let physics = Physics::default();
     let r = 5.0;
     let m = 1000.0;
-    let v = (m / (3.0f64.sqrt() * r)).sqrt();
+    let v = (physics.g * m / (3.0f64.sqrt() * r)).sqrt();
     Preset {
-        name: "Three Stars",
-        bodies: [
+        name: "Three Stars".to_string(),
+        units: UnitSystem::Simulation,
+        physics,
+        bodies: vec!(
             BodyData {
-                name: "Alpha",
-                texture: "sun",
+                name: "Alpha".to_string(),
+                texture: "sun".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.92, 0.80, 0.49),
                 radius: 2.0,
                 mass: m,
                 x: Vector3::new(0.0, 0.0, r),
-                v: Vector3::new(-v, 0.0, 0.0)
+                v: Vector3::new(-v, 0.0, 0.0),
+                orbit: None
             },
             BodyData {
-                name: "Beta",
-                texture: "bluestar",
+                name: "Beta".to_string(),
+                texture: "bluestar".to_string(),
                 color: [3.0, 3.0, 3.0],
                 trail_color: Point3::new(0.55, 0.83, 1.00),
                 radius: 2.0,
                 mass: m,
                 x: Vector3::new(- r * 3.0f64.sqrt() / 2.0, 0.0, -r / 2.0),
-                v: Vector3::new(v / 2.0, 0.0, - v * 3.0f64.sqrt() / 2.0)
+                v: Vector3::new(v / 2.0, 0.0, - v * 3.0f64.sqrt() / 2.0),
+                orbit: None
             },
             BodyData {
-                name: "Gamma",
-                texture: "yellowstar",
+                name: "Gamma".to_string(),
+                texture: "yellowstar".to_string(),
                 color: [3.0, 3.0, 3.0],
                 trail_color: Point3::new(0.99, 1.00, 0.55),
                 radius: 2.0,
                 mass: m,
                 x: Vector3::new(r * 3.0f64.sqrt() / 2.0, 0.0, -r / 2.0),
-                v: Vector3::new(v / 2.0, 0.0, v * 3.0f64.sqrt() / 2.0)
+                v: Vector3::new(v / 2.0, 0.0, v * 3.0f64.sqrt() / 2.0),
+                orbit: None
             }
-        ]
+        )
     }
 }
 
 fn figure_eight() -> Preset {
+    let physics = Physics::default();
     let x1 = Vector3::new(0.97000435669734, 0.0, -0.24308753153583) * 10.0;
     let v3 = Vector3::new(-0.93240737144104, 0.0, -0.86473146092102) * 10.0;
     let m = 1000.0;
     Preset {
-        name: "Figure Eight",
-        bodies: [
+        name: "Figure Eight".to_string(),
+        units: UnitSystem::Simulation,
+        physics,
+        bodies: vec!(
             BodyData {
-                name: "Alpha",
-                texture: "sun",
+                name: "Alpha".to_string(),
+                texture: "sun".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.92, 0.80, 0.49),
                 radius: 2.0,
                 mass: m,
                 x: x1,
-                v: -v3 / 2.0
+                v: -v3 / 2.0,
+                orbit: None
             },
             BodyData {
-                name: "Beta",
-                texture: "bluestar",
+                name: "Beta".to_string(),
+                texture: "bluestar".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.55, 0.83, 1.00),
                 radius: 2.0,
                 mass: m,
                 x: -x1,
-                v: -v3 / 2.0
+                v: -v3 / 2.0,
+                orbit: None
             },
             BodyData {
-                name: "Gamma",
-                texture: "yellowstar",
+                name: "Gamma".to_string(),
+                texture: "yellowstar".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.99, 1.00, 0.55),
                 radius: 2.0,
                 mass: m,
                 x: Vector3::zeros(),
-                v: v3
+                v: v3,
+                orbit: None
             }
-        ]
+        )
     }
 }
 
 fn lagrange_1() -> Preset {
-    let R: f64 = 40.0;
-    let m2: f64 = 10.0;
-    let m1 = 10000.0;
-    let r = 2.7076404184011786;
-    let v = (m1 / R).sqrt();
-    Preset {
-        name: "L1",
-        bodies: [
+    on_lagrange_point("L1", 40.0, 10000.0, "L1", 0)
+}
+
+fn lagrange_4() -> Preset {
+    on_lagrange_point("L4", 20.0, 1000.0, "Trojan", 3)
+}
+
+/// A sun and a planet at distance `r`, with a small body resting at the
+/// Lagrange point L`k + 1` of the pair.
+fn on_lagrange_point(name: &str, r: f64, sun_mass: f64, small_name: &str, k: usize) -> Preset {
+    let physics = Physics::default();
+    let v = (physics.g * sun_mass / r).sqrt();
+    let mut preset = Preset {
+        name: name.to_string(),
+        units: UnitSystem::Simulation,
+        physics,
+        bodies: vec!(
             BodyData {
-                name: "Sol",
-                texture: "sun",
+                name: "Sol".to_string(),
+                texture: "sun".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.92, 0.80, 0.49),
                 radius: 2.0,
-                mass: m1,
+                mass: sun_mass,
                 x: Vector3::new(0.0, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, 0.0)
+                v: Vector3::new(0.0, 0.0, 0.0),
+                orbit: None
             },
             BodyData {
-                name: "Earth",
-                texture: "earth",
+                name: "Earth".to_string(),
+                texture: "earth".to_string(),
                 color: [1.0, 1.0, 1.0],
                 trail_color: Point3::new(0.49, 0.72, 0.92),
                 radius: 0.5,
-                mass: m2,
-                x: Vector3::new(R, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, v)
-            },
-            BodyData {
-                name: "L1",
-                texture: "moon",
-                color: [1.0, 1.0, 1.0],
-                trail_color: Point3::new(0.94, 0.94, 0.94),
-                radius: 0.25,
-                mass: 0.1,
-                x: Vector3::new(R - r, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, v * (R - r) / R)
+                mass: 10.0,
+                x: Vector3::new(r, 0.0, 0.0),
+                v: Vector3::new(0.0, 0.0, v),
+                orbit: None
             }
-        ]
-    }
+        )
+    };
+    let points = LagrangePoints::new(&preset.state(), &preset.masses(), &preset.physics, 0, 1).unwrap();
+    let (x, v) = points.state(k);
+    preset.bodies.push(BodyData {
+        name: small_name.to_string(),
+        texture: "moon".to_string(),
+        color: [1.0, 1.0, 1.0],
+        trail_color: Point3::new(0.94, 0.94, 0.94),
+        radius: 0.25,
+        mass: 0.1,
+        x,
+        v,
+        orbit: None
+    });
+    preset
 }
 
-fn lagrange_4() -> Preset {
-    let r: f64 = 20.0;
-    let m1 = 1000.0;
-    let m2 = 10.0;
-    let v = (m1 / r).sqrt();
-    let x = (m1 - m2) / (m1 + m2) / 2.0;
-    let y = (3.0f64).sqrt() / 2.0;
+fn sun_earth_moon_au() -> Preset {
+    let units = UnitSystem::Astronomical;
+    let physics = Physics { g: units.g(), softening: 0.0 };
+    let m_earth = 3.003489e-6;
+    let m_moon = 3.694303e-8;
+    let d_moon = 0.002569555;
+    let v_earth = (physics.g * (1.0 + m_earth + m_moon)).sqrt();
+    let v_moon = (physics.g * (m_earth + m_moon) / d_moon).sqrt();
+    // The Earth-Moon barycenter is on a circular orbit at 1 AU.
+    let f = m_moon / (m_earth + m_moon);
     Preset {
-        name: "L4",
-        bodies: [
+        name: "Sun-Earth-Moon (AU)".to_string(),
+        units,
+        physics,
+        bodies: vec!(
             BodyData {
-                name: "Sol",
-                texture: "sun",
+                name: "Sol".to_string(),
+                texture: "sun".to_string(),
                 color: [5.0, 5.0, 5.0],
                 trail_color: Point3::new(0.92, 0.80, 0.49),
-                radius: 2.0,
-                mass: 1000.0,
+                radius: 0.05,
+                mass: 1.0,
                 x: Vector3::new(0.0, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, 0.0)
+                v: Vector3::new(0.0, 0.0, 0.0),
+                orbit: None
             },
             BodyData {
-                name: "Earth",
-                texture: "earth",
+                name: "Earth".to_string(),
+                texture: "earth".to_string(),
                 color: [1.0, 1.0, 1.0],
                 trail_color: Point3::new(0.49, 0.72, 0.92),
-                radius: 0.5,
-                mass: m2,
-                x: Vector3::new(r, 0.0, 0.0),
-                v: Vector3::new(0.0, 0.0, v)
+                radius: 0.0015,
+                mass: m_earth,
+                x: Vector3::new(1.0 - d_moon * f, 0.0, 0.0),
+                v: Vector3::new(0.0, 0.0, v_earth - v_moon * f),
+                orbit: None
             },
             BodyData {
-                name: "Trojan",
-                texture: "moon",
+                name: "Luna".to_string(),
+                texture: "moon".to_string(),
                 color: [1.0, 1.0, 1.0],
                 trail_color: Point3::new(0.94, 0.94, 0.94),
-                radius: 0.25,
-                mass: 0.1,
-                x: Vector3::new(r * x, 0.0, r * y),
-                v: Vector3::new(-v * y, 0.0, v * x)
+                radius: 0.0005,
+                mass: m_moon,
+                x: Vector3::new(1.0 + d_moon * (1.0 - f), 0.0, 0.0),
+                v: Vector3::new(0.0, 0.0, v_earth + v_moon * (1.0 - f)),
+                orbit: None
             }
-        ]
+        )
     }
 }
+
+fn earth_moon_si() -> Preset {
+    let units = UnitSystem::SI;
+    let physics = Physics { g: units.g(), softening: 0.0 };
+    let m_earth = 5.9722e24;
+    let m_moon = 7.342e22;
+    let d = 3.844e8;
+    let v = (physics.g * (m_earth + m_moon) / d).sqrt();
+    let f = m_moon / (m_earth + m_moon);
+    Preset {
+        name: "Earth-Moon (SI)".to_string(),
+        units,
+        physics,
+        bodies: vec!(
+            BodyData {
+                name: "Earth".to_string(),
+                texture: "earth".to_string(),
+                color: [1.0, 1.0, 1.0],
+                trail_color: Point3::new(0.49, 0.72, 0.92),
+                radius: 6.371e6,
+                mass: m_earth,
+                x: Vector3::new(-d * f, 0.0, 0.0),
+                v: Vector3::new(0.0, 0.0, -v * f),
+                orbit: None
+            },
+            BodyData {
+                name: "Luna".to_string(),
+                texture: "moon".to_string(),
+                color: [1.0, 1.0, 1.0],
+                trail_color: Point3::new(0.94, 0.94, 0.94),
+                radius: 1.7374e6,
+                mass: m_moon,
+                x: Vector3::new(d * (1.0 - f), 0.0, 0.0),
+                v: Vector3::new(0.0, 0.0, v * (1.0 - f)),
+                orbit: None
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units;
+
+    #[test]
+    fn unit_conversion_round_trip() {
+        let au = sun_earth_moon_au();
+        let si = au.convert(UnitSystem::SI).unwrap();
+        assert!((si.physics.g / units::G_SI - 1.0).abs() < 1e-12);
+        assert!((si.bodies[2].x.x - au.bodies[2].x.x * units::AU).abs() < 1e-3);
+        let back = si.convert(UnitSystem::Astronomical).unwrap();
+        for (a, b) in au.bodies.iter().zip(back.bodies.iter()) {
+            assert!((a.x - b.x).norm() < 1e-12 && (a.v - b.v).norm() < 1e-15);
+            assert!((a.mass / b.mass - 1.0).abs() < 1e-12);
+        }
+        assert!(sun_earth_moon().convert(UnitSystem::SI).is_err());
+    }
+
+    #[test]
+    fn bodies_placed_on_orbits() {
+        let mut p = sun_earth_moon();
+        p.bodies[2].orbit = Some(OrbitData {
+            primary: "Earth".to_string(),
+            semi_major_axis: 2.0,
+            eccentricity: 0.5,
+            inclination: 30.0,
+            node: 10.0,
+            periapsis_argument: 20.0,
+            true_anomaly: 40.0
+        });
+        p.place_orbits().unwrap();
+        let el = Elements::relative(&p.state(), &p.masses(), &p.physics, 2, 1);
+        assert!((el.semi_major_axis - 2.0).abs() < 1e-9 && (el.eccentricity - 0.5).abs() < 1e-9);
+        assert!((el.inclination.to_degrees() - 30.0).abs() < 1e-9);
+        assert!((el.true_anomaly.to_degrees() - 40.0).abs() < 1e-9);
+        p.bodies[2].orbit.as_mut().unwrap().primary = "Pluto".to_string();
+        assert!(p.place_orbits().is_err());
+    }
+
+    #[test]
+    fn earth_orbits_in_a_year() {
+        let p = sun_earth_moon_au();
+        let (mut s, m) = (p.state(), p.masses());
+        // Step until the Earth crosses the x axis again going forward.
+        let h = 0.01;
+        let mut t = 0.0;
+        loop {
+            let z = s.x[1].z;
+            s.step(h, &m, &p.physics);
+            t += h;
+            if z < 0.0 && s.x[1].z >= 0.0 {
+                break;
+            }
+        }
+        assert!((t - units::YEAR).abs() < 0.1, "period {} d", t);
+    }
+
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::presets::{Preset, BodyData};
+use crate::solver::{State, Physics};
+use crate::units::UnitSystem;
+
+/// Directory scanned for scenario files at startup.
+pub const SCENARIO_DIR: &str = "scenarios";
+
+/// Reads a scenario from a TOML file.
+pub fn load(path: &Path) -> Result<Preset, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut preset: Preset = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if preset.bodies.is_empty() {
+        return Err(format!("{}: scenario has no bodies", path.display()));
+    }
+    preset.place_orbits().map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(preset)
+}
+
+/// Writes a scenario to a TOML file.
+pub fn save(preset: &Preset, path: &Path) -> Result<(), String> {
+    let text = toml::to_string(preset).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Loads every `.toml` file in `dir`, sorted by file name. Files that fail
+/// to load are reported on stderr and skipped.
+pub fn load_dir(dir: &Path) -> Vec<Preset> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    paths.iter().filter_map(|p| match load(p) {
+        Ok(preset) => Some(preset),
+        Err(e) => {
+            eprintln!("skipping scenario {}", e);
+            None
+        }
+    }).collect()
+}
+
+/// The built-in presets followed by the scenarios found in `SCENARIO_DIR`.
+pub fn all_presets() -> Vec<Preset> {
+    let mut presets = Preset::default_presets();
+    presets.extend(load_dir(Path::new(SCENARIO_DIR)));
+    presets
+}
+
+/// File name, without the extension, for a scenario named `name`.
+pub fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect()
+}
+
+/// Snapshots the running simulation and saves it into `SCENARIO_DIR` under a
+/// file name derived from `base` that isn't taken yet. Returns the new
+/// preset, named after `base`. Like every preset, the snapshot starts at
+/// t = 0 when loaded; `state.t` isn't kept.
+pub fn save_snapshot(
+    base: &str,
+    bodies: &[BodyData],
+    state: &State,
+    masses: &[f64],
+    units: UnitSystem,
+    physics: Physics
+) -> Result<Preset, String> {
+    let dir = Path::new(SCENARIO_DIR);
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let slug = file_stem(base);
+    let (n, path) = (1..)
+        .map(|n| (n, dir.join(format!("{}-{}.toml", slug, n))))
+        .find(|(_, p)| !p.exists())
+        .unwrap();
+    let preset = Preset::from_state(format!("{} #{}", base, n), bodies, state, masses, units, physics);
+    save(&preset, &path)?;
+    Ok(preset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_scenario_loads_back() {
+        let dir = std::env::temp_dir().join(format!("tomala-scenario-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sun-earth-moon.toml");
+        let preset = Preset::default_presets().into_iter().find(|p| p.name == "Sun-Earth-Moon (AU)").unwrap();
+        save(&preset, &path).unwrap();
+        let loaded = load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.name, preset.name);
+        assert!(loaded.units == preset.units);
+        assert!(loaded.physics == preset.physics);
+        assert_eq!(loaded.bodies.len(), preset.bodies.len());
+        for (a, b) in loaded.bodies.iter().zip(preset.bodies.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.mass, b.mass);
+            assert_eq!(a.x, b.x);
+            assert_eq!(a.v, b.v);
+        }
+    }
+
+}
@@ -1,62 +1,469 @@
 use na::Vector3;
+use serde::{Serialize, Deserialize};
 use std::ops;
 
-#[derive(Copy, Clone)]
+/// Physical constants of the simulated universe.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Physics {
+    /// Gravitational constant.
+    pub g: f64,
+    /// Plummer softening length. Gravity between two bodies behaves as if
+    /// their distance were `sqrt(r^2 + softening^2)`, which keeps close
+    /// encounters finite.
+    pub softening: f64
+}
+
+impl Default for Physics {
+    fn default() -> Physics {
+        Physics { g: 1.0, softening: 0.0 }
+    }
+}
+
+#[derive(Clone)]
 pub struct State {
-    pub x: [Vector3<f64>; 3],
-    pub v: [Vector3<f64>; 3]
+    pub x: Vec<Vector3<f64>>,
+    pub v: Vec<Vector3<f64>>,
+    /// Simulated time. Integrators advance it together with the bodies.
+    pub t: f64
 }
 
-fn get_acceleration(s: State, m: &[f64; 3]) -> [Vector3<f64>; 3] {
-    let mut ret = [Vector3::new(0.0, 0.0, 0.0); 3];
-    for i in 0..3 {
-        for j in 0..3 {
+/// Gravitational acceleration of every body.
+///
+/// Massless bodies feel the others but pull on nothing, so they are left
+/// out of the inner loop and cost time linear in their number.
+#[allow(clippy::needless_range_loop)]
+pub fn get_acceleration(s: &State, m: &[f64], p: &Physics) -> Vec<Vector3<f64>> {
+    let n = s.len();
+    let eps2 = p.softening * p.softening;
+    let sources: Vec<usize> = (0..n).filter(|&j| m[j] != 0.0).collect();
+    let mut ret = vec![Vector3::new(0.0, 0.0, 0.0); n];
+    for i in 0..n {
+        for &j in sources.iter() {
             if i != j {
                 let r = s.x[j] - s.x[i];
-                let rl = r.norm();
-                ret[i] += r * m[j] / (rl * rl * rl);
+                let rl = (r.norm_squared() + eps2).sqrt();
+                ret[i] += r * (p.g * m[j]) / (rl * rl * rl);
             }
         }
     }
     ret
 }
 
+fn deriv(s: &State, m: &[f64], p: &Physics) -> State {
+    let acc = get_acceleration(s, m, p);
+    State { x: s.v.clone(), v: acc, t: 1.0 }
+}
+
+impl ops::Add<&State> for State {
+    type Output = State;
+    fn add(mut self, r: &State) -> State {
+        for i in 0..self.len() {
+            self.x[i] += r.x[i];
+            self.v[i] += r.v[i];
+        }
+        self.t += r.t;
+        self
+    }
+}
+
 impl ops::Add<State> for State {
     type Output = State;
     fn add(self, r: State) -> State {
-        let mut s = self.clone();
-        for i in 0..3 {
-            s.x[i] += r.x[i];
-            s.v[i] += r.v[i];
-        }
-        s
+        self + &r
     }
 }
 
 impl ops::Mul<f64> for State {
     type Output = State;
-    fn mul(self, h: f64) -> State {
-        let mut s = self.clone();
-        for i in 0..3 {
-            s.x[i] *= h;
-            s.v[i] *= h;
+    fn mul(mut self, h: f64) -> State {
+        for i in 0..self.len() {
+            self.x[i] *= h;
+            self.v[i] *= h;
         }
-        s
+        self.t *= h;
+        self
     }
 }
 
 impl State {
 
-    pub fn step(&mut self, h: f64, m: &[f64; 3]) {
-        fn deriv(s: State, m: &[f64; 3]) -> State {
-            let acc = get_acceleration(s, m);
-            State { x: s.v, v: acc }
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        self.x.remove(i);
+        self.v.remove(i);
+    }
+
+    pub fn step(&mut self, h: f64, m: &[f64], p: &Physics) {
+        RungeKutta4.step(self, h, m, p)
+    }
+
+    fn drift(&mut self, h: f64) {
+        for i in 0..self.len() {
+            self.x[i] += self.v[i] * h;
+        }
+        self.t += h;
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn kick(&mut self, h: f64, m: &[f64], p: &Physics) {
+        let acc = get_acceleration(self, m, p);
+        for i in 0..self.len() {
+            self.v[i] += acc[i] * h;
+        }
+    }
+
+}
+
+pub trait Integrator {
+    fn name(&self) -> &'static str;
+    /// Advances `s` by `h`, which may be negative to integrate backwards.
+    /// The symmetric integrators (leapfrog and Yoshida) retrace a forward
+    /// step exactly up to round-off.
+    fn step(&self, s: &mut State, h: f64, m: &[f64], p: &Physics);
+}
+
+pub fn integrators() -> Vec<Box<dyn Integrator>> {
+    vec!(
+        Box::new(RungeKutta4),
+        Box::new(Euler),
+        Box::new(SemiImplicitEuler),
+        Box::new(Leapfrog),
+        Box::new(Yoshida4)
+    )
+}
+
+/// Classic fourth-order Runge-Kutta.
+pub struct RungeKutta4;
+
+impl Integrator for RungeKutta4 {
+    fn name(&self) -> &'static str {
+        "RK4"
+    }
+
+    fn step(&self, s: &mut State, h: f64, m: &[f64], p: &Physics) {
+        let k1 = deriv(s, m, p) * h;
+        let k2 = deriv(&(s.clone() + k1.clone() * 0.5), m, p) * h;
+        let k3 = deriv(&(s.clone() + k2.clone() * 0.5), m, p) * h;
+        let k4 = deriv(&(s.clone() + &k3), m, p) * h;
+        *s = s.clone() + (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (1.0 / 6.0)
+    }
+}
+
+/// Explicit (forward) Euler, first order.
+pub struct Euler;
+
+impl Integrator for Euler {
+    fn name(&self) -> &'static str {
+        "Euler"
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn step(&self, s: &mut State, h: f64, m: &[f64], p: &Physics) {
+        let acc = get_acceleration(s, m, p);
+        for i in 0..s.len() {
+            s.x[i] += s.v[i] * h;
+            s.v[i] += acc[i] * h;
+        }
+        s.t += h;
+    }
+}
+
+/// Semi-implicit (symplectic) Euler: velocities first, then positions
+/// using the updated velocities.
+pub struct SemiImplicitEuler;
+
+impl Integrator for SemiImplicitEuler {
+    fn name(&self) -> &'static str {
+        "Semi-implicit Euler"
+    }
+
+    fn step(&self, s: &mut State, h: f64, m: &[f64], p: &Physics) {
+        s.kick(h, m, p);
+        s.drift(h);
+    }
+}
+
+/// Velocity Verlet in kick-drift-kick form, second order and symplectic.
+pub struct Leapfrog;
+
+impl Integrator for Leapfrog {
+    fn name(&self) -> &'static str {
+        "Leapfrog"
+    }
+
+    fn step(&self, s: &mut State, h: f64, m: &[f64], p: &Physics) {
+        s.kick(h / 2.0, m, p);
+        s.drift(h);
+        s.kick(h / 2.0, m, p);
+    }
+}
+
+/// Yoshida's fourth-order symplectic integrator, built from three leapfrog
+/// substeps with coefficients chosen to cancel the third-order error.
+pub struct Yoshida4;
+
+impl Integrator for Yoshida4 {
+    fn name(&self) -> &'static str {
+        "Yoshida 4"
+    }
+
+    fn step(&self, s: &mut State, h: f64, m: &[f64], p: &Physics) {
+        let cbrt2 = 2.0f64.cbrt();
+        let w1 = 1.0 / (2.0 - cbrt2);
+        let w0 = -cbrt2 / (2.0 - cbrt2);
+        let c = [w1 / 2.0, (w0 + w1) / 2.0, (w0 + w1) / 2.0, w1 / 2.0];
+        let d = [w1, w0, w1];
+        for i in 0..3 {
+            s.drift(c[i] * h);
+            s.kick(d[i] * h, m, p);
+        }
+        s.drift(c[3] * h);
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AdaptiveStats {
+    pub accepted: usize,
+    pub rejected: usize
+}
+
+impl ops::AddAssign for AdaptiveStats {
+    fn add_assign(&mut self, r: AdaptiveStats) {
+        self.accepted += r.accepted;
+        self.rejected += r.rejected;
+    }
+}
+
+/// Dormand-Prince 5(4) embedded Runge-Kutta method with adaptive step size.
+///
+/// The step size chosen by the last call to `advance` is remembered and used
+/// as the initial guess for the next one.
+pub struct DormandPrince {
+    pub atol: f64,
+    pub rtol: f64,
+    h: f64
+}
+
+const DP_A: [[f64; 6]; 6] = [
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+    [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0]
+];
+/// Difference between the fifth and fourth order weights.
+const DP_E: [f64; 7] = [
+    71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0, 22.0 / 525.0, -1.0 / 40.0
+];
+
+impl DormandPrince {
+
+    pub fn new(atol: f64, rtol: f64) -> DormandPrince {
+        DormandPrince { atol, rtol, h: 0.0 }
+    }
+
+    /// Advances `s` by `span` units of simulated time, taking as many
+    /// internal steps as the tolerances require.
+    #[allow(clippy::needless_range_loop)]
+    pub fn advance(&mut self, s: &mut State, span: f64, m: &[f64], p: &Physics) -> AdaptiveStats {
+        let mut stats = AdaptiveStats::default();
+        if span == 0.0 {
+            return stats;
+        }
+        let dir = span.signum();
+        let mut remaining = span.abs();
+        if self.h <= 0.0 {
+            self.h = remaining;
+        }
+        let mut k1 = deriv(s, m, p);
+        while remaining > 0.0 {
+            let h = self.h.min(remaining);
+            let mut k = vec![k1.clone()];
+            let mut next = s.clone();
+            for stage in 0..6 {
+                next = s.clone();
+                for j in 0..=stage {
+                    next = next + k[j].clone() * (DP_A[stage][j] * h * dir);
+                }
+                k.push(deriv(&next, m, p));
+            }
+            let err = self.error_norm(s, &next, &k, h);
+            if err <= 1.0 || h <= MIN_STEP {
+                *s = next;
+                // The last stage is evaluated at the new solution, so its
+                // derivative is reused as the first stage of the next step.
+                k1 = k.pop().unwrap();
+                remaining -= h;
+                stats.accepted += 1;
+            } else {
+                stats.rejected += 1;
+            }
+            let factor = if err.is_finite() {
+                0.9 * err.powf(-0.2)
+            } else {
+                0.2
+            };
+            self.h = (h * factor.clamp(0.2, 5.0)).max(MIN_STEP);
+        }
+        stats
+    }
+
+    fn error_norm(&self, y0: &State, y1: &State, k: &[State], h: f64) -> f64 {
+        let mut sum = 0.0;
+        let mut n = 0;
+        for i in 0..y0.len() {
+            let mut ex = Vector3::zeros();
+            let mut ev = Vector3::zeros();
+            for j in 0..7 {
+                ex += k[j].x[i] * (DP_E[j] * h);
+                ev += k[j].v[i] * (DP_E[j] * h);
+            }
+            for (e, a, b) in [(ex, y0.x[i], y1.x[i]), (ev, y0.v[i], y1.v[i])].iter() {
+                for c in 0..3 {
+                    let sc = self.atol + self.rtol * a[c].abs().max(b[c].abs());
+                    sum += (e[c] / sc) * (e[c] / sc);
+                    n += 1;
+                }
+            }
+        }
+        (sum / n.max(1) as f64).sqrt()
+    }
+
+}
+
+/// Below this step size the error estimate is ignored and the step is taken
+/// anyway, so a singular configuration can't stall the caller.
+const MIN_STEP: f64 = 1e-12;
+
+/// Future positions of every body: `samples + 1` points per body, evenly
+/// spaced over the next `span` of simulated time and starting with the
+/// current one. A copy of `s` is integrated with `steps` RK4 steps between
+/// samples, so the cost is fixed even when bodies pass close to each other.
+#[allow(clippy::needless_range_loop)]
+pub fn predict(s: &State, m: &[f64], p: &Physics, span: f64, samples: usize, steps: usize) -> Vec<Vec<Vector3<f64>>> {
+    let mut s = s.clone();
+    let mut paths: Vec<Vec<Vector3<f64>>> = s.x.iter().map(|x| vec!(*x)).collect();
+    let h = span / (samples * steps) as f64;
+    for _ in 0..samples {
+        for _ in 0..steps {
+            s.step(h, m, p);
+        }
+        for i in 0..s.len() {
+            paths[i].push(s.x[i]);
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::Preset;
+
+    #[test]
+    fn integrators_advance_time() {
+        let p = &Preset::default_presets()[0];
+        let m = p.masses();
+        for integrator in integrators() {
+            let mut s = p.state();
+            for _ in 0..10 {
+                integrator.step(&mut s, 0.001, &m, &p.physics);
+            }
+            assert!((s.t - 0.01).abs() < 1e-15, "{}: t = {}", integrator.name(), s.t);
+        }
+        let mut s = p.state();
+        DormandPrince::new(1e-9, 1e-9).advance(&mut s, 0.5, &m, &p.physics);
+        assert!((s.t - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn symmetric_integrators_retrace_their_steps() {
+        let p = Preset::default_presets().into_iter().find(|p| p.name == "Figure Eight").unwrap();
+        let m = p.masses();
+        let integrators: [Box<dyn Integrator>; 2] = [Box::new(Leapfrog), Box::new(Yoshida4)];
+        for integrator in integrators.iter() {
+            let start = p.state();
+            let mut s = start.clone();
+            for _ in 0..1000 {
+                integrator.step(&mut s, 0.001, &m, &p.physics);
+            }
+            assert!((s.x[0] - start.x[0]).norm() > 0.1);
+            for _ in 0..1000 {
+                integrator.step(&mut s, -0.001, &m, &p.physics);
+            }
+            for i in 0..s.len() {
+                assert!((s.x[i] - start.x[i]).norm() < 1e-10, "{}: position error {}", integrator.name(), (s.x[i] - start.x[i]).norm());
+                assert!((s.v[i] - start.v[i]).norm() < 1e-10, "{}: velocity error {}", integrator.name(), (s.v[i] - start.v[i]).norm());
+            }
+            assert!(s.t.abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn adaptive_agrees_with_fine_rk4() {
+        let p = Preset::default_presets().into_iter().find(|p| p.name == "Figure Eight").unwrap();
+        let m = p.masses();
+        let mut fine = p.state();
+        for _ in 0..20000 {
+            RungeKutta4.step(&mut fine, 1e-4, &m, &p.physics);
+        }
+        let mut s = p.state();
+        DormandPrince::new(1e-10, 1e-10).advance(&mut s, 2.0, &m, &p.physics);
+        for i in 0..s.len() {
+            assert!((s.x[i] - fine.x[i]).norm() < 1e-7, "position error {}", (s.x[i] - fine.x[i]).norm());
+            assert!((s.v[i] - fine.v[i]).norm() < 1e-7, "velocity error {}", (s.v[i] - fine.v[i]).norm());
+        }
+    }
+
+    #[test]
+    fn adaptive_steps_crowd_into_close_encounters() {
+        // The triangle of Three Stars breaks up after t = 23 into a series
+        // of close passes.
+        let p = Preset::default_presets().into_iter().find(|p| p.name == "Three Stars").unwrap();
+        let m = p.masses();
+        let mut s = p.state();
+        let mut adaptive = DormandPrince::new(1e-9, 1e-9);
+        let quiet = adaptive.advance(&mut s, 0.5, &m, &p.physics);
+        adaptive.advance(&mut s, 22.5, &m, &p.physics);
+        let mut busiest = AdaptiveStats::default();
+        let mut total = AdaptiveStats::default();
+        for _ in 0..14 {
+            let stats = adaptive.advance(&mut s, 0.5, &m, &p.physics);
+            if stats.accepted > busiest.accepted {
+                busiest = stats;
+            }
+            total += stats;
+        }
+        assert!(busiest.accepted > 5 * quiet.accepted, "{:?} vs {:?}", busiest, quiet);
+        assert!(total.rejected > 0);
+        assert!((s.t - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    #[allow(clippy::needless_range_loop)]
+    fn prediction_leaves_the_state_alone() {
+        let p = &Preset::default_presets()[0];
+        let (s, m) = (p.state(), p.masses());
+        let paths = predict(&s, &m, &p.physics, 1.0, 10, 20);
+        assert_eq!(paths.len(), s.len());
+        assert!(paths.iter().all(|path| path.len() == 11));
+        assert_eq!(s.t, 0.0);
+        let mut stepped = s.clone();
+        for _ in 0..200 {
+            stepped.step(0.005, &m, &p.physics);
+        }
+        for i in 0..s.len() {
+            assert_eq!(paths[i][0], s.x[i]);
+            assert!((paths[i][10] - stepped.x[i]).norm() < 1e-12);
         }
-        let k1 = deriv(*self, m) * h;
-        let k2 = deriv(*self + k1 * 0.5, m) * h;
-        let k3 = deriv(*self + k2 * 0.5, m) * h;
-        let k4 = deriv(*self + k3, m) * h;
-        *self = *self + (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (1.0 / 6.0)
     }
 
 }
@@ -0,0 +1,190 @@
+use serde::{Serialize, Deserialize};
+
+/// Gravitational constant in m³/(kg s²).
+pub const G_SI: f64 = 6.67430e-11;
+/// Heliocentric gravitational constant GM☉ in m³/s².
+pub const GM_SUN: f64 = 1.32712440018e20;
+/// Solar mass in kg, chosen so that `G_SI * SOLAR_MASS == GM_SUN`.
+pub const SOLAR_MASS: f64 = GM_SUN / G_SI;
+/// Astronomical unit in m.
+pub const AU: f64 = 1.495978707e11;
+/// Day in s.
+pub const DAY: f64 = 86400.0;
+/// Julian year in days.
+pub const YEAR: f64 = 365.25;
+
+/// Units in which the positions, velocities, masses and times of a preset
+/// are given.
+#[derive(Clone, Copy, PartialEq, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    /// Dimensionless units with G = 1, as used by the original presets.
+    #[default]
+    Simulation,
+    /// Meters, kilograms and seconds.
+    SI,
+    /// Astronomical units, solar masses and days.
+    Astronomical
+}
+
+impl UnitSystem {
+
+    pub fn all() -> [UnitSystem; 3] {
+        [UnitSystem::Simulation, UnitSystem::SI, UnitSystem::Astronomical]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "simulation",
+            UnitSystem::SI => "SI",
+            UnitSystem::Astronomical => "astronomical"
+        }
+    }
+
+    /// Length unit in meters, mass unit in kilograms and time unit in
+    /// seconds, or `None` for the dimensionless simulation units.
+    pub fn scale(&self) -> Option<(f64, f64, f64)> {
+        match *self {
+            UnitSystem::Simulation => None,
+            UnitSystem::SI => Some((1.0, 1.0, 1.0)),
+            UnitSystem::Astronomical => Some((AU, SOLAR_MASS, DAY))
+        }
+    }
+
+    /// The gravitational constant expressed in these units.
+    pub fn g(&self) -> f64 {
+        match self.scale() {
+            Some((l, m, t)) => G_SI * m * t * t / (l * l * l),
+            None => 1.0
+        }
+    }
+
+    pub fn length(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "",
+            UnitSystem::SI => "m",
+            UnitSystem::Astronomical => "AU"
+        }
+    }
+
+    pub fn mass(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "",
+            UnitSystem::SI => "kg",
+            UnitSystem::Astronomical => "Msun"
+        }
+    }
+
+    pub fn time(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "",
+            UnitSystem::SI => "s",
+            UnitSystem::Astronomical => "d"
+        }
+    }
+
+    pub fn velocity(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "",
+            UnitSystem::SI => "m/s",
+            UnitSystem::Astronomical => "AU/d"
+        }
+    }
+
+    pub fn momentum(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "",
+            UnitSystem::SI => "kg m/s",
+            UnitSystem::Astronomical => "Msun AU/d"
+        }
+    }
+
+    pub fn energy(&self) -> &'static str {
+        match *self {
+            UnitSystem::Simulation => "",
+            UnitSystem::SI => "J",
+            UnitSystem::Astronomical => "Msun AU^2/d^2"
+        }
+    }
+
+    /// Factors converting lengths, masses and times from these units into
+    /// `to`. Fails if only one of the two systems is dimensionless.
+    pub fn conversion(&self, to: UnitSystem) -> Result<(f64, f64, f64), String> {
+        if *self == to {
+            return Ok((1.0, 1.0, 1.0));
+        }
+        match (self.scale(), to.scale()) {
+            (Some((l1, m1, t1)), Some((l2, m2, t2))) => Ok((l1 / l2, m1 / m2, t1 / t2)),
+            _ => Err(format!("can't convert between {} and {} units", self.name(), to.name()))
+        }
+    }
+
+    /// Formats a simulated time, in days or years where the units allow it.
+    pub fn format_time(&self, t: f64) -> String {
+        let seconds = match self.scale() {
+            Some((_, _, unit)) => t * unit,
+            None => return format!("{:.3}", t)
+        };
+        let days = seconds / DAY;
+        if days.abs() >= 2.0 * YEAR {
+            format!("{:.2} yr", days / YEAR)
+        } else if seconds.abs() >= DAY {
+            format!("{:.2} d", days)
+        } else if seconds.abs() >= 3600.0 {
+            format!("{:.2} h", seconds / 3600.0)
+        } else {
+            format!("{:.1} s", seconds)
+        }
+    }
+
+}
+
+/// Formats a value followed by its unit, switching to scientific notation
+/// for very large or small magnitudes.
+pub fn quantity(value: f64, unit: &str) -> String {
+    let a = value.abs();
+    let number = if a != 0.0 && !(1e-3..1e5).contains(&a) {
+        format!("{:.3e}", value)
+    } else {
+        format!("{:.4}", value)
+    };
+    if unit.is_empty() {
+        number
+    } else {
+        format!("{} {}", number, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaussian_gravitational_constant() {
+        // k² from the definition of the astronomical unit.
+        let k = 0.01720209895;
+        assert!((UnitSystem::Astronomical.g() / (k * k) - 1.0).abs() < 1e-6);
+        assert_eq!(UnitSystem::SI.g(), G_SI);
+        assert_eq!(UnitSystem::Simulation.g(), 1.0);
+    }
+
+    #[test]
+    fn conversion_round_trip() {
+        let (l, m, t) = UnitSystem::Astronomical.conversion(UnitSystem::SI).unwrap();
+        assert_eq!((l, m, t), (AU, SOLAR_MASS, DAY));
+        let (l2, m2, t2) = UnitSystem::SI.conversion(UnitSystem::Astronomical).unwrap();
+        assert!((l * l2 - 1.0).abs() < 1e-15 && (m * m2 - 1.0).abs() < 1e-15 && (t * t2 - 1.0).abs() < 1e-15);
+        assert!(UnitSystem::Simulation.conversion(UnitSystem::SI).is_err());
+        assert!(UnitSystem::Simulation.conversion(UnitSystem::Simulation).is_ok());
+    }
+
+    #[test]
+    fn time_formatting() {
+        assert_eq!(UnitSystem::Astronomical.format_time(10.0), "10.00 d");
+        assert_eq!(UnitSystem::Astronomical.format_time(1461.0), "4.00 yr");
+        assert_eq!(UnitSystem::SI.format_time(2.0 * DAY), "2.00 d");
+        assert_eq!(UnitSystem::SI.format_time(30.0), "30.0 s");
+        assert_eq!(UnitSystem::Simulation.format_time(1.5), "1.500");
+    }
+
+}
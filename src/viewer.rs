@@ -0,0 +1,2237 @@
+use tomala_space_program::solver::{self, State, Physics, Integrator, DormandPrince, AdaptiveStats};
+use tomala_space_program::presets::{Preset, BodyData};
+use tomala_space_program::scenario;
+use tomala_space_program::diagnostics::{self, Diagnostics};
+use tomala_space_program::collision::{Collisions, Collision, CollisionPolicy};
+use tomala_space_program::units::{self, UnitSystem};
+use tomala_space_program::history::{History, Snapshot};
+use tomala_space_program::orbit::{self, Elements};
+use tomala_space_program::lagrange::LagrangePoints;
+use tomala_space_program::particles::Particles;
+use tomala_space_program::chaos::{self, Chaos};
+
+use std::path::Path;
+use std::collections::vec_deque::VecDeque;
+use std::time::Instant;
+
+use na::{Vector2, Vector3, Point2, Point3, Rotation3};
+use kiss3d::window::Window;
+use kiss3d::event::{Key, WindowEvent, Action, MouseButton};
+use kiss3d::light::Light;
+use kiss3d::resource::{TextureManager};
+use kiss3d::scene::SceneNode;
+use kiss3d::camera::{ArcBall, Camera};
+use kiss3d::text::Font;
+use kiss3d::conrod;
+
+pub fn run() {
+    let mut window = Window::new("Tomala Space Program");
+    let mut body_spheres: Vec<SceneNode> = Vec::new();
+    let mut sky = window.add_sphere(200.0);
+
+    let mut textures = TextureManager::new();
+    let font = Font::default();
+
+    let mut camera = ArcBall::new(Point3::new(4.0, 4.0, 0.0), Point3::new(0.0, 0.0, 0.0));
+
+    let mut presets = scenario::all_presets();
+    let integrators = solver::integrators();
+    let mut adaptive = DormandPrince::new(1e-9, 1e-9);
+    let mut collisions = Collisions::new(CollisionPolicy::Ignore);
+
+    textures.add(Path::new("tex/earth.jpg"), "earth");
+    textures.add(Path::new("tex/sun.jpg"), "sun");
+    textures.add(Path::new("tex/moon.jpg"), "moon");
+    textures.add(Path::new("tex/sky.jpg"), "sky");
+    textures.add(Path::new("tex/yellowstar.jpg"), "yellowstar");
+    textures.add(Path::new("tex/bluestar.jpg"), "bluestar");
+
+    sky.set_texture(textures.get("sky").unwrap());
+    sky.enable_backface_culling(false);
+    sky.set_color(5.0, 5.0, 5.0);
+
+    let mut ids = Ids::new(window.conrod_ui_mut().widget_id_generator());
+    window.conrod_ui_mut().theme = theme();
+
+    let mut state = presets[0].state();
+    let mut masses = presets[0].masses();
+    let mut physics = presets[0].physics;
+    let mut bodies = presets[0].bodies.clone();
+    let mut reference = Diagnostics::new(&state, &masses, &physics);
+    let (mut scene_scale, time_scale) = view_scale(&presets[0]);
+    load_bodies(&mut window, &mut body_spheres, &mut ids, &mut textures, &bodies, scene_scale);
+
+    let mut gui_state = GuiState::new();
+    gui_state.body_panel_open.resize(state.len(), false);
+    gui_state.primaries = default_primaries(&state, &masses);
+    gui_state.time_scale = time_scale;
+
+    let mut trails: Vec<VecDeque<Point3<f32>>> = vec![VecDeque::new(); state.len()];
+    let mut last_frame = Instant::now();
+    let mut history = History::new(HISTORY_LENGTH);
+    let mut drag: Option<Drag> = None;
+    let mut particles = Particles::new();
+    let mut chaos: Option<Chaos> = None;
+    let mut shadow_adaptive = DormandPrince::new(1e-9, 1e-9);
+    // A perturbed copy integrated next to the state, drawn as ghosts.
+    let mut twin: Option<State> = None;
+    let mut twin_adaptive = DormandPrince::new(1e-9, 1e-9);
+    let mut twin_spheres: Vec<SceneNode> = Vec::new();
+    let mut twin_trails: Vec<VecDeque<Point3<f32>>> = Vec::new();
+    // Time and the values of `plot_series` for the last frames.
+    let mut samples: VecDeque<(f64, Vec<f64>)> = VecDeque::new();
+    // Future paths of the bodies, and the time and frame they were
+    // predicted in.
+    let mut prediction: Vec<Vec<Vector3<f64>>> = Vec::new();
+    let mut predicted_at: Option<(f64, FixState)> = None;
+
+    while window.render_with_camera(&mut camera) {
+        let scene = |x: Vector3<f64>| -> Point3<f32> { (x * scene_scale).map(|x| x as f32).into() };
+        for i in 0..state.len() {
+            body_spheres[i].set_local_translation(scene(state.x[i]).coords.into());
+        }
+        window.set_light(Light::Absolute(scene(state.x[0])));
+        for i in 0..state.len() {
+            trails[i].push_front(scene(state.x[i]));
+            if trails[i].len() > gui_state.trail_length {
+                trails[i].pop_back();
+            }
+            let color = bodies[i].trail_color;
+            for (i, (a, b)) in trails[i].iter().zip(trails[i].iter().skip(1)).enumerate().rev() {
+                let l = 1.0 - (i as f32) / (gui_state.trail_length as f32);
+                window.draw_line(a, b, &(color * l));
+            }
+        }
+        if let Some(l) = gui_state.lagrange_pair().and_then(|(a, b)| LagrangePoints::new(&state, &masses, &physics, a, b)) {
+            // Crosses sized to the pair, labelled on screen.
+            let size = (state.x[gui_state.lagrange_secondary] - state.x[gui_state.lagrange_primary]).norm() * 0.02;
+            let color = Point3::new(0.8, 0.8, 0.3);
+            let size_on_screen = window.size().map(|c| c as f32);
+            for (k, x) in l.points.iter().enumerate() {
+                for axis in 0..3 {
+                    let mut d = Vector3::zeros();
+                    d[axis] = size;
+                    window.draw_line(&scene(x - d), &scene(x + d), &color);
+                }
+                let p = scene(*x);
+                if (p - camera.eye()).dot(&(camera.at() - camera.eye())) > 0.0 {
+                    let screen = camera.project(&p, &size_on_screen);
+                    window.draw_text(&format!("L{}", k + 1), &Point2::new(screen.x, size_on_screen.y - screen.y), 36.0, &font, &color);
+                }
+            }
+        }
+        for x in particles.x.iter() {
+            window.draw_point(&scene(*x), &Point3::new(0.8, 0.8, 0.8));
+        }
+        let ghosts = twin.as_ref().map_or(0, |t| t.len());
+        load_twin(&mut window, &mut twin_spheres, &bodies[..ghosts], scene_scale);
+        twin_trails.resize(ghosts, VecDeque::new());
+        if let Some(ref t) = twin {
+            for i in 0..t.len() {
+                twin_spheres[i].set_local_translation(scene(t.x[i]).coords.into());
+                twin_trails[i].push_front(scene(t.x[i]));
+                if twin_trails[i].len() > gui_state.trail_length {
+                    twin_trails[i].pop_back();
+                }
+                let color = bodies[i].trail_color * 0.5;
+                for (k, (a, b)) in twin_trails[i].iter().zip(twin_trails[i].iter().skip(1)).enumerate() {
+                    let l = 1.0 - (k as f32) / (gui_state.trail_length as f32);
+                    window.draw_line(a, b, &(color * l));
+                }
+            }
+        }
+        let handle_time = HANDLE_TIME * gui_state.time_scale;
+        if gui_state.edit_mode {
+            for i in 0..state.len() {
+                let normal = orbit_normal(&state, i, gui_state.primaries[i]);
+                let color = match drag {
+                    Some(Drag::Velocity(j, _)) if j == i => Point3::new(1.0, 1.0, 1.0),
+                    _ => Point3::new(0.6, 0.6, 0.6)
+                };
+                draw_arrow(&mut window, scene(state.x[i]), scene(state.x[i] + state.v[i] * handle_time), &normal, &color);
+            }
+        }
+        if let Some(d) = drag {
+            // One orbit of the dragged body ahead, or a fixed span if it is
+            // unbound or has no primary.
+            let i = d.body();
+            let period = gui_state.primaries[i]
+                .map(|p| Elements::relative(&state, &masses, &physics, i, p).period)
+                .filter(|t| t.is_finite())
+                .unwrap_or(f64::INFINITY);
+            let span = period.min(PREDICTION_SPAN * gui_state.time_scale);
+            let paths = predict(&state, &masses, &physics, span, &gui_state);
+            for (j, path) in paths.iter().enumerate() {
+                let color = bodies[j].trail_color * if j == i { 1.0 } else { 0.5 };
+                for (a, b) in path.iter().zip(path.iter().skip(1)) {
+                    window.draw_line(&scene(*a), &scene(*b), &color);
+                }
+            }
+        } else if gui_state.predict {
+            // Kept until the simulation moves on by a sample, since the view
+            // follows it along the predicted paths in the meantime.
+            let stale = match predicted_at {
+                Some((t, fix)) => (state.t - t).abs() >= gui_state.horizon / PREDICTION_SAMPLES as f64
+                    || fix != gui_state.fix || prediction.len() != state.len(),
+                None => true
+            };
+            if stale {
+                prediction = predict(&state, &masses, &physics, gui_state.horizon, &gui_state);
+                predicted_at = Some((state.t, gui_state.fix));
+            }
+            // Dashed and dimmer than the trails.
+            for (j, path) in prediction.iter().enumerate() {
+                let color = bodies[j].trail_color * 0.6;
+                for k in (0..path.len() - 1).step_by(2) {
+                    window.draw_line(&scene(path[k]), &scene(path[k + 1]), &color);
+                }
+            }
+        }
+        // Simulated time still to be covered, driven by the wall clock.
+        let now = Instant::now();
+        let wall = now.duration_since(last_frame).as_secs_f64().min(MAX_FRAME);
+        last_frame = now;
+        let rate = gui_state.speed * gui_state.time_scale;
+        if !gui_state.paused {
+            gui_state.pending += rate * wall;
+        }
+        if gui_state.step_frame {
+            gui_state.pending += rate * FRAME;
+        }
+        // Reversed time integrates with negative steps; `pending` is always
+        // the amount of time still to be covered in the current direction.
+        let dir = if gui_state.reverse { -1.0 } else { 1.0 };
+        let mut finishing = false;
+        if let Some(end) = gui_state.run_until {
+            if gui_state.pending >= (end - state.t) * dir {
+                gui_state.pending = ((end - state.t) * dir).max(0.0);
+                finishing = true;
+            }
+        }
+        // The simulation holds still while a body is being dragged.
+        if drag.is_some() {
+            gui_state.pending = 0.0;
+        }
+        let mut events = Vec::new();
+        if gui_state.pending > 0.0 {
+            // Resuming from an earlier moment replaces its future.
+            if let Some(i) = gui_state.scrub.take() {
+                history.truncate(i + 1);
+            }
+            collisions.policy = CollisionPolicy::all()[gui_state.collision_policy];
+            if gui_state.adaptive {
+                adaptive.atol = 10.0f64.powf(gui_state.tolerance);
+                adaptive.rtol = adaptive.atol;
+                let span = gui_state.pending * dir;
+                gui_state.adaptive_stats = particles.advance(&mut state, &masses, |s, m| adaptive.advance(s, span, m, &physics));
+                if let Some(ref mut c) = chaos {
+                    shadow_adaptive.atol = adaptive.atol;
+                    shadow_adaptive.rtol = adaptive.rtol;
+                    shadow_adaptive.advance(c.shadow(), span, &masses, &physics);
+                }
+                if let Some(ref mut t) = twin {
+                    twin_adaptive.atol = adaptive.atol;
+                    twin_adaptive.rtol = adaptive.rtol;
+                    twin_adaptive.advance(t, span, &masses, &physics);
+                }
+                gui_state.pending = 0.0;
+                events = handle_collisions(&mut collisions, &mut state, &mut masses, &mut bodies, &mut gui_state.last_collision);
+                follow_merges(&events, &state, &mut chaos, &mut twin, &gui_state);
+            } else {
+                let integrator = &integrators[gui_state.selected_integrator];
+                let h = STEP * gui_state.time_scale / gui_state.substeps as f64;
+                while gui_state.pending >= h && !stopped(&collisions, &events) {
+                    particles.advance(&mut state, &masses, |s, m| integrator.step(s, h * dir, m, &physics));
+                    if let Some(ref mut c) = chaos {
+                        integrator.step(c.shadow(), h * dir, &masses, &physics);
+                    }
+                    if let Some(ref mut t) = twin {
+                        integrator.step(t, h * dir, &masses, &physics);
+                    }
+                    gui_state.pending -= h;
+                    let new_events = handle_collisions(&mut collisions, &mut state, &mut masses, &mut bodies, &mut gui_state.last_collision);
+                    follow_merges(&new_events, &state, &mut chaos, &mut twin, &gui_state);
+                    events.extend(new_events);
+                }
+                // The last step of a run is shortened to end exactly on time.
+                if finishing && gui_state.pending > 0.0 && !stopped(&collisions, &events) {
+                    let h = gui_state.pending * dir;
+                    particles.advance(&mut state, &masses, |s, m| integrator.step(s, h, m, &physics));
+                    if let Some(ref mut c) = chaos {
+                        integrator.step(c.shadow(), h, &masses, &physics);
+                    }
+                    if let Some(ref mut t) = twin {
+                        integrator.step(t, h, &masses, &physics);
+                    }
+                    gui_state.pending = 0.0;
+                    let new_events = handle_collisions(&mut collisions, &mut state, &mut masses, &mut bodies, &mut gui_state.last_collision);
+                    follow_merges(&new_events, &state, &mut chaos, &mut twin, &gui_state);
+                    events.extend(new_events);
+                }
+            }
+        }
+        if finishing && gui_state.pending == 0.0 {
+            gui_state.run_until = None;
+            gui_state.paused = true;
+        }
+        let radii: Vec<f64> = bodies.iter().map(|b| b.radius as f64).collect();
+        particles.absorb(&state, &radii);
+        if let Some(ref mut c) = chaos {
+            c.renormalize(&state);
+        }
+        for e in events.iter().filter(|e| e.merged) {
+            remove_body(&mut window, e.b, &mut body_spheres, &mut trails, &mut gui_state);
+        }
+        if events.iter().any(|e| e.merged) {
+            load_bodies(&mut window, &mut body_spheres, &mut ids, &mut textures, &bodies, scene_scale);
+        }
+        if stopped(&collisions, &events) {
+            gui_state.paused = true;
+            gui_state.pending = 0.0;
+        }
+        if gui_state.scrub.is_none() {
+            history.record(Snapshot { state: state.clone(), masses: masses.clone(), bodies: bodies.clone() });
+        }
+        gui_state.history_length = history.len();
+        gui(&mut window.conrod_ui_mut().set_widgets(), &ids, &mut masses, &mut physics, &mut gui_state, &mut state, &reference, &bodies, &presets, &integrators, &samples);
+        // Edits aren't drift, so the diagnostics are measured from them.
+        if gui_state.physics_changed || gui_state.bodies_edited {
+            reference = Diagnostics::new(&state, &masses, &physics);
+        }
+        if gui_state.physics_changed || gui_state.bodies_edited || gui_state.horizon_changed {
+            predicted_at = None;
+        }
+        if let Some(seeding) = gui_state.seed_particles.take() {
+            seed_particles(&mut particles, seeding, &state, &masses, &physics, &gui_state);
+        }
+
+        let can_drag = gui_state.edit_mode && gui_state.editable() && !window.is_conrod_ui_capturing_mouse();
+        for mut e in window.events().iter() {
+            match e.value {
+                WindowEvent::Key(Key::Space, Action::Press, _) =>
+                    gui_state.paused = !gui_state.paused,
+                WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) if can_drag => {
+                    drag = cursor_ray(&window, &camera, scene_scale)
+                        .and_then(|ray| pick(ray, &state, &bodies, &gui_state.primaries, handle_time));
+                    if let Some(d) = drag {
+                        trails[d.body()].clear();
+                        e.inhibited = true;
+                    }
+                }
+                WindowEvent::MouseButton(MouseButton::Button1, Action::Release, _) if drag.is_some() => {
+                    drag = None;
+                    e.inhibited = true;
+                }
+                // Keeps the camera from rotating while dragging.
+                WindowEvent::CursorPos(..) if drag.is_some() => e.inhibited = true,
+                _ => ()
+            }
+        }
+        if let (Some(d), Some(ray)) = (drag, cursor_ray(&window, &camera, scene_scale)) {
+            predicted_at = None;
+            match d {
+                Drag::Position(i, offset, point, normal) => if let Some(p) = ray_plane(ray, &point, &normal) {
+                    state.x[i] = p + offset;
+                },
+                Drag::Velocity(i, normal) => if let Some(p) = ray_plane(ray, &state.x[i], &normal) {
+                    state.v[i] = (p - state.x[i]) / handle_time;
+                }
+            }
+            reference = Diagnostics::new(&state, &masses, &physics);
+        }
+
+        if let Some(f) = gui_state.follow {
+            camera.set_at(scene(state.x[f]));
+        }
+
+        if let FixState::Fix(f, rot) = gui_state.fix {
+            let pos = state.x[f];
+            for i in 0..state.len() {
+                state.x[i] -= pos;
+            }
+            for x in particles.x.iter_mut() {
+                *x -= pos;
+            }
+            if let Some(ref mut c) = chaos {
+                for x in c.shadow().x.iter_mut() {
+                    *x -= pos;
+                }
+            }
+            if let Some(ref mut t) = twin {
+                for x in t.x.iter_mut() {
+                    *x -= pos;
+                }
+            }
+            for r in rot {
+                if let Some(trans) = Rotation3::rotation_between(&state.x[r], &Vector3::new(1.0, 0.0, 0.0)) {
+                    for i in 0..state.len() {
+                        state.x[i] = trans * state.x[i];
+                        state.v[i] = trans * state.v[i];
+                    }
+                    for i in 0..particles.len() {
+                        particles.x[i] = trans * particles.x[i];
+                        particles.v[i] = trans * particles.v[i];
+                    }
+                    if let Some(ref mut c) = chaos {
+                        let shadow = c.shadow();
+                        for i in 0..shadow.len() {
+                            shadow.x[i] = trans * shadow.x[i];
+                            shadow.v[i] = trans * shadow.v[i];
+                        }
+                    }
+                    if let Some(ref mut t) = twin {
+                        for i in 0..t.len() {
+                            t.x[i] = trans * t.x[i];
+                            t.v[i] = trans * t.v[i];
+                        }
+                    }
+                }
+            }
+        }
+
+        sky.set_local_translation(camera.eye().coords.into());
+        if gui_state.reset || gui_state.preset_changed {
+            let preset = &presets[gui_state.selected_preset];
+            state = preset.state();
+            masses = preset.masses();
+            physics = preset.physics;
+            bodies = preset.bodies.clone();
+            reference = Diagnostics::new(&state, &masses, &physics);
+            collisions.reset();
+            particles.clear();
+            adaptive = DormandPrince::new(adaptive.atol, adaptive.rtol);
+            gui_state.last_collision.clear();
+            gui_state.units = preset.units;
+            gui_state.pending = 0.0;
+            gui_state.run_until = None;
+            let (s, t) = view_scale(preset);
+            scene_scale = s;
+            gui_state.time_scale = t;
+            load_bodies(&mut window, &mut body_spheres, &mut ids, &mut textures, &bodies, scene_scale);
+            gui_state.body_panel_open.resize(state.len(), false);
+            gui_state.primaries = default_primaries(&state, &masses);
+            trails.resize(state.len(), VecDeque::new());
+            gui_state.follow = None;
+            gui_state.fix = FixState::None;
+            drag = None;
+            gui_state.horizon = PREDICTION_HORIZON * t;
+            gui_state.lagrange_primary = 0;
+            gui_state.lagrange_secondary = 1;
+            gui_state.placed_body = state.len() - 1;
+            gui_state.fit_lagrange();
+            predicted_at = None;
+        }
+        if let (true, Some(snapshot)) = (gui_state.scrubbed, gui_state.scrub.and_then(|i| history.get(i))) {
+            state = snapshot.state.clone();
+            masses = snapshot.masses.clone();
+            if bodies.len() != snapshot.bodies.len() {
+                gui_state.body_panel_open.resize(snapshot.bodies.len(), false);
+                gui_state.primaries = default_primaries(&snapshot.state, &snapshot.masses);
+                gui_state.fit_lagrange();
+                trails.resize(snapshot.bodies.len(), VecDeque::new());
+                gui_state.follow = None;
+                gui_state.fix = FixState::None;
+            }
+            bodies = snapshot.bodies.clone();
+            drag = None;
+            // Particles aren't kept in the history.
+            particles.clear();
+            predicted_at = None;
+            load_bodies(&mut window, &mut body_spheres, &mut ids, &mut textures, &bodies, scene_scale);
+            collisions.reset();
+            gui_state.paused = true;
+            gui_state.pending = 0.0;
+            gui_state.run_until = None;
+        }
+        if gui_state.reset || gui_state.preset_changed {
+            history.clear();
+            gui_state.scrub = None;
+        }
+        if gui_state.save_scenario {
+            let base = presets[gui_state.selected_preset].name.clone();
+            match scenario::save_snapshot(&base, &bodies, &state, &masses, gui_state.units, physics) {
+                Ok(preset) => presets.push(preset),
+                Err(e) => eprintln!("could not save scenario: {}", e)
+            }
+        }
+        // Any change to the bodies starts the measurement over.
+        let restart = gui_state.physics_changed || gui_state.bodies_edited || drag.is_some()
+            || events.iter().any(|e| e.merged) || gui_state.reset || gui_state.preset_changed || gui_state.scrubbed;
+        if !gui_state.measure_chaos {
+            chaos = None;
+        } else if chaos.is_none() || restart {
+            chaos = Some(Chaos::new(&state));
+        }
+        gui_state.chaos = chaos.as_ref().map(|c| (c.lyapunov(), c.megno(), c.mean_megno()));
+        if !gui_state.twin || gui_state.twin_body >= state.len() {
+            twin = None;
+        } else if twin.is_none() || restart || gui_state.twin_changed {
+            twin = new_twin(&state, &gui_state);
+            twin_adaptive = DormandPrince::new(adaptive.atol, adaptive.rtol);
+            for trail in twin_trails.iter_mut() {
+                trail.clear();
+            }
+        }
+        gui_state.twin_distance = twin.as_ref().map(|t| chaos::phase_distance(&state, t));
+        if restart {
+            samples.clear();
+        }
+        if samples.back().map_or(true, |(t, _)| *t != state.t) {
+            samples.push_back((state.t, plot_sample(&state, &masses, &physics, &reference)));
+            if samples.len() > PLOT_LENGTH {
+                samples.pop_front();
+            }
+        }
+        if gui_state.reset || gui_state.clear_trails || gui_state.preset_changed || gui_state.scrubbed {
+            for trail in trails.iter_mut() {
+                trail.clear();
+            }
+        }
+    }
+}
+
+/// Number of frames covered by the plot.
+const PLOT_LENGTH: usize = 600;
+/// Simulated time per step, in units of the time scale of the preset, before
+/// dividing into substeps.
+const STEP: f64 = 0.001;
+/// Number of past frames that can be scrubbed back to.
+const HISTORY_LENGTH: usize = 3600;
+/// Nominal duration of a frame in seconds, used for single steps.
+const FRAME: f64 = 1.0 / 60.0;
+/// Longest frame in seconds the simulation catches up with, so that a stall
+/// doesn't turn into a huge jump.
+const MAX_FRAME: f64 = 0.1;
+
+/// Velocity handles point to where a body would be after this much time at
+/// constant velocity, in units of the time scale of the preset.
+const HANDLE_TIME: f64 = 1.0;
+/// Longest span of the trajectory predicted while dragging, in units of the
+/// time scale of the preset.
+const PREDICTION_SPAN: f64 = 50.0;
+const PREDICTION_SAMPLES: usize = 300;
+/// Default span of the prediction overlay, in units of the time scale of
+/// the preset.
+const PREDICTION_HORIZON: f64 = 20.0;
+/// Longest RK4 step of a prediction, in units of the time scale of the
+/// preset. Long horizons take longer steps instead, up to a fixed budget
+/// per sample.
+const PREDICTION_STEP: f64 = 0.01;
+/// Largest angle in radians between the cursor ray and a velocity handle
+/// or small body that still picks it.
+const PICK_ANGLE: f64 = 0.015;
+
+/// Scene units per length unit and simulated time per unit of the original
+/// dimensionless presets. Presets in physical units are scaled to look and
+/// move on screen like those: the farthest body from the center of mass is
+/// drawn 20 units away, and an orbit at that distance takes as many frames
+/// as one around 1000 mass units at distance 20.
+fn view_scale(preset: &Preset) -> (f64, f64) {
+    if preset.units == UnitSystem::Simulation {
+        return (1.0, 1.0);
+    }
+    let (s, m) = (preset.state(), preset.masses());
+    let com = diagnostics::center_of_mass(&s, &m);
+    let size = s.x.iter().map(|x| (x - com).norm()).fold(0.0, f64::max);
+    let total: f64 = m.iter().sum();
+    if size == 0.0 || total == 0.0 {
+        return (1.0, 1.0);
+    }
+    let period = (size.powi(3) / (preset.physics.g * total)).sqrt();
+    (20.0 / size, period / (20.0f64.powi(3) / 1000.0).sqrt())
+}
+
+/// Applies the collision policy using the radii of the displayed spheres.
+/// Bodies merged away are removed from `bodies` and the radii of the merged
+/// ones updated; the last collision is described in `log`.
+fn handle_collisions(
+    collisions: &mut Collisions,
+    state: &mut State,
+    masses: &mut Vec<f64>,
+    bodies: &mut Vec<BodyData>,
+    log: &mut String
+) -> Vec<Collision> {
+    let mut radii: Vec<f64> = bodies.iter().map(|b| b.radius as f64).collect();
+    let events = collisions.handle(state, masses, &mut radii);
+    for e in events.iter() {
+        *log = format!("{} hit {} at v = {:.2}{}", bodies[e.a].name, bodies[e.b].name,
+                       e.relative_speed, if e.merged { ", merged" } else { "" });
+        if e.merged {
+            bodies.remove(e.b);
+        }
+    }
+    for (body, r) in bodies.iter_mut().zip(radii) {
+        body.radius = r as f32;
+    }
+    events
+}
+
+/// A merge leaves the shadow of the chaos measurement and the twin with a
+/// body the state no longer has, so both start over before the next step.
+fn follow_merges(events: &[Collision], state: &State, chaos: &mut Option<Chaos>, twin: &mut Option<State>, gui_state: &GuiState) {
+    chaos::restart_after_merges(chaos, state, events);
+    if twin.is_some() && events.iter().any(|e| e.merged) {
+        *twin = new_twin(state, gui_state);
+    }
+}
+
+/// The twin chosen in the panel, unless its body is gone.
+fn new_twin(state: &State, gui_state: &GuiState) -> Option<State> {
+    if gui_state.twin_body >= state.len() {
+        return None;
+    }
+    Some(chaos::twin(state, gui_state.twin_body, gui_state.twin_component, 10.0f64.powf(gui_state.twin_size)))
+}
+
+/// A body being dragged in edit mode. Positions move on a plane, given by a
+/// point and a normal, keeping the offset between the body and the point
+/// the cursor grabbed; velocities move on the plane through the body.
+#[derive(Clone, Copy)]
+enum Drag {
+    Position(usize, Vector3<f64>, Vector3<f64>, Vector3<f64>),
+    Velocity(usize, Vector3<f64>)
+}
+
+impl Drag {
+    fn body(&self) -> usize {
+        match *self {
+            Drag::Position(i, ..) | Drag::Velocity(i, _) => i
+        }
+    }
+}
+
+/// Origin and direction of the ray from the camera through the cursor, in
+/// simulation units.
+fn cursor_ray(window: &Window, camera: &ArcBall, scene_scale: f64) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let (x, y) = window.cursor_pos()?;
+    let size = window.size();
+    let (origin, dir) = camera.unproject(&Point2::new(x as f32, y as f32), &Vector2::new(size.x as f32, size.y as f32));
+    Some((origin.coords.map(|c| c as f64) / scene_scale, dir.map(|c| c as f64)))
+}
+
+/// Where the ray meets the plane in front of the camera, if it isn't
+/// nearly parallel to it.
+fn ray_plane((origin, dir): (Vector3<f64>, Vector3<f64>), point: &Vector3<f64>, normal: &Vector3<f64>) -> Option<Vector3<f64>> {
+    let d = dir.dot(normal);
+    if d.abs() < 1e-3 {
+        return None;
+    }
+    let t = (point - origin).dot(normal) / d;
+    if t > 0.0 { Some(origin + dir * t) } else { None }
+}
+
+/// Normal of the plane body `i` orbits its primary in, or of the x-z plane
+/// the presets orbit in if it has no primary or moves straight at it.
+fn orbit_normal(s: &State, i: usize, primary: Option<usize>) -> Vector3<f64> {
+    primary
+        .and_then(|p| (s.x[i] - s.x[p]).cross(&(s.v[i] - s.v[p])).try_normalize(0.0))
+        .unwrap_or_else(Vector3::y)
+}
+
+/// The body or velocity handle under the cursor nearest to the camera.
+/// Handles are picked from a small cone around the ray, and so are bodies
+/// too small to hit.
+fn pick(
+    ray: (Vector3<f64>, Vector3<f64>),
+    s: &State,
+    bodies: &[BodyData],
+    primaries: &[Option<usize>],
+    handle_time: f64
+) -> Option<Drag> {
+    let (origin, dir) = ray;
+    let mut best = None;
+    let mut nearest = f64::INFINITY;
+    for i in 0..s.len() {
+        let normal = orbit_normal(s, i, primaries[i]);
+        let targets = [(s.x[i], bodies[i].radius as f64, false), (s.x[i] + s.v[i] * handle_time, 0.0, true)];
+        for &(center, radius, handle) in targets.iter() {
+            let depth = (center - origin).dot(&dir);
+            let miss = (center - origin - dir * depth).norm();
+            if depth <= 0.0 || depth >= nearest || miss > radius.max(PICK_ANGLE * depth) {
+                continue;
+            }
+            let point = primaries[i].map_or(s.x[i], |p| s.x[p]);
+            best = if handle {
+                Some(Drag::Velocity(i, normal))
+            } else {
+                ray_plane(ray, &point, &normal).map(|p| Drag::Position(i, s.x[i] - p, point, normal))
+            };
+            nearest = depth;
+        }
+    }
+    best
+}
+
+/// Future paths of the bodies over `span`, in the frame of the fixed body if
+/// there is one.
+fn predict(s: &State, m: &[f64], p: &Physics, span: f64, gui_state: &GuiState) -> Vec<Vec<Vector3<f64>>> {
+    let per_sample = span / PREDICTION_SAMPLES as f64;
+    let steps = (per_sample / (PREDICTION_STEP * gui_state.time_scale)).ceil().clamp(1.0, 100.0) as usize;
+    let mut paths = solver::predict(s, m, p, span, PREDICTION_SAMPLES, steps);
+    if let FixState::Fix(f, rot) = gui_state.fix {
+        // Each sample is seen as the view will show it then: with the fixed
+        // body in place and the co-rotating body in the same direction.
+        let center = paths[f].clone();
+        let turns: Vec<Rotation3<f64>> = (0..center.len())
+            .map(|k| rot
+                .and_then(|r| Rotation3::rotation_between(&(paths[r][k] - center[k]), &(paths[r][0] - center[0])))
+                .unwrap_or_else(Rotation3::identity))
+            .collect();
+        for path in paths.iter_mut() {
+            for k in 0..path.len() {
+                path[k] = turns[k] * (path[k] - center[k]) + center[0];
+            }
+        }
+    }
+    paths
+}
+
+/// Replaces the particles with a new cloud around the Lagrange point picked
+/// in the Lagrange panel, or a disk around the primary of the pair there.
+fn seed_particles(particles: &mut Particles, seeding: Seeding, s: &State, m: &[f64], p: &Physics, gui_state: &GuiState) {
+    let (a, b) = (gui_state.lagrange_primary, gui_state.lagrange_secondary);
+    let l = match LagrangePoints::new(s, m, p, a, b) {
+        Some(l) => l,
+        None => return
+    };
+    let r = (s.x[b] - s.x[a]).norm();
+    let (count, seed) = (gui_state.particle_count, s.t.to_bits());
+    particles.clear();
+    match seeding {
+        Seeding::Lagrange => particles.seed_lagrange(&l, gui_state.lagrange_point, count, 0.1 * r, seed),
+        Seeding::Disk => particles.seed_disk((s.x[a], s.v[a]), p.g * m[a], &l.rotation, (0.2 * r, 1.5 * r), count, seed)
+    }
+}
+
+/// Line with an arrowhead lying in the plane with the given normal.
+fn draw_arrow(window: &mut Window, from: Point3<f32>, to: Point3<f32>, normal: &Vector3<f64>, color: &Point3<f32>) {
+    window.draw_line(&from, &to, color);
+    let back = (from - to) * 0.15;
+    let side = back.cross(&normal.map(|c| c as f32)) * 0.5;
+    window.draw_line(&to, &(to + back + side), color);
+    window.draw_line(&to, &(to + back - side), color);
+}
+
+fn stopped(collisions: &Collisions, events: &[Collision]) -> bool {
+    collisions.policy == CollisionPolicy::Stop && !events.is_empty()
+}
+
+/// Drops the sphere, trail and panel of a body that has been removed from
+/// the simulation, and stops following or fixing it.
+fn remove_body(
+    window: &mut Window,
+    i: usize,
+    body_spheres: &mut Vec<SceneNode>,
+    trails: &mut Vec<VecDeque<Point3<f32>>>,
+    state: &mut GuiState
+) {
+    window.remove_node(&mut body_spheres.remove(i));
+    trails.remove(i);
+    state.body_panel_open.remove(i);
+    state.primaries.remove(i);
+    let shift = |f: usize| if f > i { Some(f - 1) } else if f == i { None } else { Some(f) };
+    for p in state.primaries.iter_mut() {
+        *p = p.and_then(shift);
+    }
+    state.follow = state.follow.and_then(shift);
+    state.fit_lagrange();
+    state.fix = match state.fix {
+        FixState::Fix(f, rot) => match shift(f) {
+            Some(f) => FixState::Fix(f, rot.and_then(shift)),
+            None => FixState::None
+        },
+        FixState::None => FixState::None
+    };
+}
+
+/// Keeps one wireframe sphere per body of the twin, since kiss3d can't draw
+/// translucent ones.
+fn load_twin(window: &mut Window, spheres: &mut Vec<SceneNode>, bodies: &[BodyData], scene_scale: f64) {
+    while spheres.len() > bodies.len() {
+        window.remove_node(&mut spheres.pop().unwrap());
+    }
+    while spheres.len() < bodies.len() {
+        let mut sphere = window.add_sphere(1.0);
+        sphere.set_surface_rendering_activation(false);
+        sphere.set_lines_width(1.0);
+        spheres.push(sphere);
+    }
+    for (sphere, body) in spheres.iter_mut().zip(bodies.iter()) {
+        let c = body.trail_color;
+        sphere.set_color(c.x, c.y, c.z);
+        let r = body.radius * scene_scale as f32;
+        sphere.set_local_scale(r, r, r);
+    }
+}
+
+fn load_bodies(
+    window: &mut Window,
+    body_spheres: &mut Vec<SceneNode>,
+    ids: &mut Ids,
+    textures: &mut TextureManager,
+    bodies: &[BodyData],
+    scene_scale: f64
+) {
+    let n = bodies.len();
+    while body_spheres.len() > n {
+        window.remove_node(&mut body_spheres.pop().unwrap());
+    }
+    while body_spheres.len() < n {
+        body_spheres.push(window.add_sphere(1.0));
+    }
+    for (sphere, body_data) in body_spheres.iter_mut().zip(bodies.iter()) {
+        let texture = textures.get(&body_data.texture).or_else(|| textures.get("moon"));
+        sphere.set_texture(texture.unwrap());
+        sphere.set_color(body_data.color[0], body_data.color[1], body_data.color[2]);
+        let r = body_data.radius * scene_scale as f32;
+        sphere.set_local_scale(r, r, r);
+    }
+
+    let mut gen = window.conrod_ui_mut().widget_id_generator();
+    ids.mass.resize(n, &mut gen);
+    ids.velocity.resize(n, &mut gen);
+    ids.body_panel.resize(n, &mut gen);
+    ids.follow.resize(n, &mut gen);
+    ids.fix.resize(n, &mut gen);
+    ids.fix_rot.resize(n, &mut gen);
+    ids.primary.resize(n, &mut gen);
+    ids.components.resize(6 * n, &mut gen);
+    ids.component_units.resize(n, &mut gen);
+    ids.elements.resize(6 * n, &mut gen);
+    ids.orbit.resize(n, &mut gen);
+    ids.plot_series.resize(2 + n * (n - 1) / 2 + n, &mut gen);
+    ids.plot_lines.resize(2 + n * (n - 1) / 2 + n, &mut gen);
+}
+
+widget_ids! {
+    pub struct Ids {
+        general,
+        time,
+        momentum,
+        energy,
+        adaptive_stats,
+        last_collision,
+        chaos,
+        preset,
+        integrator,
+        speed,
+        reverse,
+        substeps,
+        until,
+        run_until,
+        timeline,
+        adaptive,
+        tolerance,
+        gravity,
+        softening,
+        collision_policy,
+        trail_length,
+        predict,
+        horizon,
+        edit_paused_only,
+        edit_mode,
+        measure_chaos,
+        pause_play_button,
+        step_frame,
+        momentum_zero,
+        reset,
+        clear_trails,
+        save_scenario,
+        lagrange,
+        lagrange_primary,
+        lagrange_secondary,
+        show_lagrange,
+        co_rotate,
+        placed_body,
+        lagrange_point,
+        place,
+        particle_count,
+        seed_lagrange,
+        seed_disk,
+        twin_panel,
+        twin,
+        twin_body,
+        twin_component,
+        twin_size,
+        twin_distance,
+        plot,
+        plot_max,
+        plot_frame,
+        plot_min,
+        plot_time,
+        plot_series[],
+        plot_lines[],
+        body_panel[],
+        mass[],
+        velocity[],
+        follow[],
+        fix[],
+        fix_rot[],
+        components[],
+        component_units[],
+        primary[],
+        elements[],
+        orbit[]
+    }
+}
+
+fn theme() -> conrod::Theme {
+    conrod::Theme {
+        background_color: conrod::color::rgba(0.0, 0.0, 0.0, 0.4),
+        label_color: conrod::color::rgba(1.0, 1.0, 1.0, 0.6),
+        shape_color: conrod::color::rgba(0.05, 0.33, 0.51, 0.6),
+        ..conrod::Theme::default()
+    }
+}
+
+struct GuiState {
+    general_open: bool,
+    body_panel_open: Vec<bool>,
+    /// Body whose orbit elements are shown relative to, for each body.
+    primaries: Vec<Option<usize>>,
+    /// Orbit entered in a body panel this frame, applied by `gui`.
+    edited_orbit: Option<Elements>,
+    selected_preset: usize,
+    preset_changed: bool,
+    selected_integrator: usize,
+    paused: bool,
+    step_frame: bool,
+    run_until: Option<f64>,
+    until: f64,
+    pending: f64,
+    history_length: usize,
+    scrub: Option<usize>,
+    scrubbed: bool,
+    reset: bool,
+    clear_trails: bool,
+    save_scenario: bool,
+    trail_length: usize,
+    /// Body panels only accept edits while the simulation is paused.
+    edit_paused_only: bool,
+    /// Bodies and their velocity handles can be dragged in the 3D view.
+    edit_mode: bool,
+    /// A mass, position or velocity was changed in the panels this frame.
+    bodies_edited: bool,
+    /// Draw the predicted paths of the bodies `horizon` ahead.
+    predict: bool,
+    horizon: f64,
+    horizon_changed: bool,
+    lagrange_open: bool,
+    /// Pair whose Lagrange points are marked when `show_lagrange` is set.
+    lagrange_primary: usize,
+    lagrange_secondary: usize,
+    show_lagrange: bool,
+    /// Body placed at Lagrange point `lagrange_point` by the Place button.
+    placed_body: usize,
+    lagrange_point: usize,
+    particle_count: usize,
+    /// Particles to seed this frame, replacing the current ones.
+    seed_particles: Option<Seeding>,
+    measure_chaos: bool,
+    /// Lyapunov exponent, MEGNO and its mean, while measured.
+    chaos: Option<(f64, f64, f64)>,
+    twin_open: bool,
+    twin: bool,
+    /// The twin starts with coordinate `twin_component` of `twin_body`
+    /// nudged by 10^`twin_size` of the largest one.
+    twin_body: usize,
+    twin_component: usize,
+    twin_size: f64,
+    twin_changed: bool,
+    twin_distance: Option<f64>,
+    plot_open: bool,
+    /// Which of `plot_series` are drawn.
+    plot_series: Vec<bool>,
+    speed: f64,
+    reverse: bool,
+    time_scale: f64,
+    substeps: usize,
+    adaptive: bool,
+    tolerance: f64,
+    adaptive_stats: AdaptiveStats,
+    physics_changed: bool,
+    collision_policy: usize,
+    last_collision: String,
+    units: UnitSystem,
+    follow: Option<usize>,
+    fix: FixState
+}
+
+impl GuiState {
+    fn editable(&self) -> bool {
+        self.paused || !self.edit_paused_only
+    }
+
+    /// Keeps the bodies picked in the Lagrange panel in range after bodies
+    /// were removed or another preset loaded.
+    fn fit_lagrange(&mut self) {
+        let last = self.primaries.len().max(1) - 1;
+        self.lagrange_primary = self.lagrange_primary.min(last);
+        self.lagrange_secondary = self.lagrange_secondary.min(last);
+        self.placed_body = self.placed_body.min(last);
+    }
+
+    /// The pair whose Lagrange points are shown, if it is a valid one.
+    fn lagrange_pair(&self) -> Option<(usize, usize)> {
+        let (a, b) = (self.lagrange_primary, self.lagrange_secondary);
+        if self.show_lagrange && a != b && a.max(b) < self.primaries.len() {
+            Some((a, b))
+        } else {
+            None
+        }
+    }
+
+    fn new() -> GuiState {
+        GuiState {
+            general_open: true,
+            body_panel_open: Vec::new(),
+            primaries: Vec::new(),
+            edited_orbit: None,
+            selected_preset: 0,
+            preset_changed: true,
+            selected_integrator: 0,
+            paused: false,
+            step_frame: false,
+            run_until: None,
+            until: 0.0,
+            pending: 0.0,
+            history_length: 0,
+            scrub: None,
+            scrubbed: false,
+            reset: false,
+            clear_trails: false,
+            save_scenario: false,
+            trail_length: 500,
+            edit_paused_only: false,
+            edit_mode: false,
+            bodies_edited: false,
+            predict: false,
+            horizon: PREDICTION_HORIZON,
+            horizon_changed: false,
+            lagrange_open: false,
+            lagrange_primary: 0,
+            lagrange_secondary: 1,
+            show_lagrange: false,
+            placed_body: 0,
+            lagrange_point: 0,
+            particle_count: 500,
+            seed_particles: None,
+            measure_chaos: false,
+            chaos: None,
+            twin_open: false,
+            twin: false,
+            twin_body: 0,
+            twin_component: 0,
+            twin_size: -8.0,
+            twin_changed: false,
+            twin_distance: None,
+            plot_open: false,
+            plot_series: Vec::new(),
+            speed: 0.6,
+            reverse: false,
+            time_scale: 1.0,
+            substeps: 10,
+            adaptive: false,
+            tolerance: -9.0,
+            adaptive_stats: AdaptiveStats::default(),
+            physics_changed: false,
+            collision_policy: 0,
+            last_collision: String::new(),
+            units: UnitSystem::Simulation,
+            follow: None,
+            fix: FixState::None
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Seeding {
+    Lagrange,
+    Disk
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FixState {
+    Fix(usize, Option<usize>),
+    None
+}
+
+impl FixState {
+
+    fn fix_center(&self) -> Option<usize> {
+        match *self {
+            FixState::Fix(f, _) => Some(f),
+            _ => None
+        }
+    }
+
+    fn fix_rot(&self) -> Option<usize> {
+        match *self {
+            FixState::Fix(_, f) => f,
+            _ => None
+        }
+    }
+
+}
+
+const MARGIN: conrod::Scalar = 10.0;
+
+fn gui(
+    ui: &mut conrod::UiCell,
+    ids: &Ids,
+    masses: &mut [f64],
+    physics: &mut Physics,
+    state: &mut GuiState,
+    body_state: &mut State,
+    reference: &Diagnostics,
+    bodies: &[BodyData],
+    presets: &Vec<Preset>,
+    integrators: &Vec<Box<dyn Integrator>>,
+    samples: &VecDeque<(f64, Vec<f64>)>
+) {
+    use conrod::{widget, Borderable, Labelable, Positionable, Sizeable, Widget};
+
+    const WIDTH: conrod::Scalar = 200.0;
+
+    state.bodies_edited = false;
+
+    plot_panel(ui, ids, state, bodies, samples);
+
+    let (gen, genev) = widget::CollapsibleArea::new(state.general_open, "general")
+        .top_right()
+        .label_font_size(12)
+        .w_h(WIDTH, 20.0)
+        .set(ids.general, ui);
+
+    for e in genev {
+        state.general_open = e.is_open()
+    }
+    for area in gen {
+        let canvas = widget::Canvas::new()
+            .h(815.0)
+            .pad(MARGIN);
+
+        area.set(canvas, ui);
+
+        let units = state.units;
+        let time = match state.run_until {
+            Some(end) => format!("Time: {} (until {})", units.format_time(body_state.t), units.format_time(end)),
+            None => format!("Time: {}", units.format_time(body_state.t))
+        };
+        widget::Text::new(&time)
+            .font_size(12)
+            .w(WIDTH)
+            .parent(area.id)
+            .top_left()
+            .set(ids.time, ui);
+
+        let diagnostics = Diagnostics::new(body_state, masses, physics);
+        let momentum = diagnostics.momentum;
+
+        widget::Text::new(&*format!("Total momentum:\n x: {}\n y: {}\n z: {}",
+                                    units::quantity(momentum.x, units.momentum()),
+                                    units::quantity(momentum.y, units.momentum()),
+                                    units::quantity(momentum.z, units.momentum())))
+            .font_size(12)
+            .w(WIDTH)
+            .parent(area.id)
+            .set(ids.momentum, ui);
+
+        let drift = diagnostics.drift(reference);
+        widget::Text::new(&*format!("Total energy: {}\nEnergy drift: {:.2e}\nAngular momentum drift: {:.2e}",
+                                    units::quantity(diagnostics.energy, units.energy()),
+                                    drift.energy, drift.angular_momentum))
+            .font_size(12)
+            .w(WIDTH)
+            .parent(area.id)
+            .set(ids.energy, ui);
+
+        let stats = if state.adaptive {
+            format!("Steps: {} ok, {} rejected", state.adaptive_stats.accepted, state.adaptive_stats.rejected)
+        } else {
+            String::new()
+        };
+        widget::Text::new(&stats)
+            .font_size(12)
+            .w(WIDTH)
+            .parent(area.id)
+            .set(ids.adaptive_stats, ui);
+
+        widget::Text::new(&state.last_collision)
+            .font_size(12)
+            .w(WIDTH)
+            .parent(area.id)
+            .set(ids.last_collision, ui);
+
+        let chaos = match state.chaos {
+            Some((lyapunov, megno, mean)) => {
+                let per_time = if units.time().is_empty() { String::new() } else { format!(" /{}", units.time()) };
+                format!("Lyapunov exponent: {:.3e}{}\nMEGNO: {:.3}, mean {:.3}", lyapunov, per_time, megno, mean)
+            },
+            None => String::new()
+        };
+        widget::Text::new(&chaos)
+            .font_size(12)
+            .w(WIDTH)
+            .parent(area.id)
+            .set(ids.chaos, ui);
+
+        state.preset_changed = false;
+        state.physics_changed = false;
+
+        let preset_names: Vec<&str> = presets.iter().map(|p| p.name.as_str()).collect();
+        for i in widget::DropDownList::new(&preset_names, Some(state.selected_preset))
+            .parent(area.id)
+            .align_left()
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.preset, ui)
+        {
+            state.selected_preset = i;
+            state.preset_changed = true;
+        }
+
+        let integrator_names: Vec<&str> = integrators.iter().map(|i| i.name()).collect();
+        for i in widget::DropDownList::new(&integrator_names, Some(state.selected_integrator))
+            .parent(area.id)
+            .align_left()
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.integrator, ui)
+        {
+            state.selected_integrator = i;
+        }
+
+        let speed = units.format_time(state.speed * state.time_scale);
+        for s in widget::Slider::new(state.speed.log10(), -2.0, 1.0)
+            .parent(area.id)
+            .align_left()
+            .w((area.width - 2.0 * MARGIN) * 2.0 / 3.0)
+            .h(30.0)
+            .label(&format!("speed: {} / s", speed))
+            .label_font_size(12)
+            .set(ids.speed, ui)
+        {
+            state.speed = 10.0f64.powf(s);
+        }
+
+        for s in widget::Toggle::new(state.reverse)
+            .parent(area.id)
+            .label("reverse")
+            .right(0.0)
+            .y_relative(0.0)
+            .w((area.width - 2.0 * MARGIN) / 3.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.reverse, ui)
+        {
+            state.reverse = s;
+        }
+
+        for s in widget::Slider::new(state.substeps as f64, 1.0, 1000.0)
+            .skew(2.0)
+            .parent(area.id)
+            .align_left()
+            .down(0.0)
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label(&format!("substeps: {}", state.substeps))
+            .label_font_size(12)
+            .set(ids.substeps, ui)
+        {
+            state.substeps = s as usize;
+        }
+
+        // Physical times are entered in days.
+        let day = units.scale().map_or(1.0, |(_, _, t)| units::DAY / t);
+        let label = if day == 1.0 { "until" } else { "until [d]" };
+        for t in widget::NumberDialer::new(state.until / day, 0.0, 99999.0, 1)
+            .parent(area.id)
+            .label(label)
+            .border(0.0)
+            .align_left()
+            .down(0.0)
+            .w((area.width - 2.0 * MARGIN) * 2.0 / 3.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.until, ui)
+        {
+            state.until = t * day;
+        }
+
+        if widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 3.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .label("Run")
+            .label_font_size(12)
+            .set(ids.run_until, ui)
+            .was_clicked()
+            && state.until != body_state.t
+        {
+            state.run_until = Some(state.until);
+            state.reverse = state.until < body_state.t;
+            state.paused = false;
+        }
+
+        let last = state.history_length.max(1) - 1;
+        state.scrubbed = false;
+        for i in widget::Slider::new(state.scrub.unwrap_or(last) as f64, 0.0, last as f64)
+            .parent(area.id)
+            .align_left()
+            .down(0.0)
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label(&format!("timeline: {}", units.format_time(body_state.t)))
+            .label_font_size(12)
+            .set(ids.timeline, ui)
+        {
+            state.scrub = Some(i.round() as usize);
+            state.scrubbed = true;
+        }
+
+        for s in widget::Toggle::new(state.adaptive)
+            .parent(area.id)
+            .label("adaptive")
+            .align_left()
+            .down(0.0)
+            .w((area.width - 2.0 * MARGIN) / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.adaptive, ui)
+        {
+            state.adaptive = s;
+        }
+
+        for tol in widget::NumberDialer::new(state.tolerance, -15.0, -1.0, 0)
+            .parent(area.id)
+            .label("tol 1e")
+            .border(0.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .w((area.width - 2.0 * MARGIN) / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.tolerance, ui)
+        {
+            state.tolerance = tol;
+        }
+
+        let g_scale = dialer_scale(units, presets[state.selected_preset].physics.g);
+        for g in widget::NumberDialer::new(physics.g / g_scale, 0.0, 9999.0, 3)
+            .parent(area.id)
+            .label(&unit_label("G", g_scale, ""))
+            .border(0.0)
+            .align_left_of(ids.adaptive)
+            .down_from(ids.adaptive, 0.0)
+            .w((area.width - 2.0 * MARGIN) / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.gravity, ui)
+        {
+            physics.g = g * g_scale;
+            state.physics_changed = true;
+        }
+
+        let largest = bodies.iter().map(|b| b.radius as f64).fold(0.0, f64::max);
+        let eps_scale = dialer_scale(units, largest);
+        for eps in widget::NumberDialer::new(physics.softening / eps_scale, 0.0, 100.0, 3)
+            .parent(area.id)
+            .label(&unit_label("soft", eps_scale, units.length()))
+            .border(0.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .w((area.width - 2.0 * MARGIN) / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.softening, ui)
+        {
+            physics.softening = eps * eps_scale;
+            state.physics_changed = true;
+        }
+
+        let policy_names: Vec<String> = CollisionPolicy::all().iter()
+            .map(|p| format!("collisions: {}", p.name()))
+            .collect();
+        for i in widget::DropDownList::new(&policy_names, Some(state.collision_policy))
+            .parent(area.id)
+            .align_left_of(ids.gravity)
+            .down_from(ids.gravity, 0.0)
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.collision_policy, ui)
+        {
+            state.collision_policy = i;
+        }
+
+        for len in widget::NumberDialer::new(state.trail_length as f64, 0.0, 9999.0, 0)
+            .parent(area.id)
+            .label("trail length")
+            .border(0.0)
+            .align_left()
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.trail_length, ui)
+        {
+            state.trail_length = len as usize;
+        }
+
+        for s in widget::Toggle::new(state.predict)
+            .parent(area.id)
+            .label("predict")
+            .align_left()
+            .down(0.0)
+            .w((area.width - 2.0 * MARGIN) / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.predict, ui)
+        {
+            state.predict = s;
+        }
+
+        state.horizon_changed = false;
+        let label = if day == 1.0 { "ahead" } else { "ahead [d]" };
+        for t in widget::NumberDialer::new(state.horizon / day, 0.0, 99999.0, 1)
+            .parent(area.id)
+            .label(label)
+            .border(0.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .w((area.width - 2.0 * MARGIN) / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.horizon, ui)
+        {
+            state.horizon = t * day;
+            state.horizon_changed = true;
+        }
+
+        for s in widget::Toggle::new(state.edit_paused_only)
+            .parent(area.id)
+            .label("edit bodies only while paused")
+            .align_left_of(ids.predict)
+            .down_from(ids.predict, 0.0)
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.edit_paused_only, ui)
+        {
+            state.edit_paused_only = s;
+        }
+
+        for s in widget::Toggle::new(state.edit_mode)
+            .parent(area.id)
+            .label("drag bodies and velocities")
+            .align_left()
+            .down(0.0)
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.edit_mode, ui)
+        {
+            state.edit_mode = s;
+        }
+
+        for s in widget::Toggle::new(state.measure_chaos)
+            .parent(area.id)
+            .label("measure chaos")
+            .align_left()
+            .down(0.0)
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.measure_chaos, ui)
+        {
+            state.measure_chaos = s;
+        }
+
+        if widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 4.0)
+            .label(if state.paused { "Play" } else { "Pause" })
+            .label_font_size(12)
+            .set(ids.pause_play_button, ui)
+            .was_clicked()
+        {
+            state.paused = !state.paused;
+        }
+
+        state.step_frame = widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 4.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .label("Step")
+            .label_font_size(12)
+            .enabled(state.paused)
+            .set(ids.step_frame, ui)
+            .was_clicked();
+
+        if widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 4.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .label("p 0")
+            .label_font_size(12)
+            .enabled(state.editable())
+            .set(ids.momentum_zero, ui)
+            .was_clicked()
+        {
+            let m: f64 = masses.iter().sum();
+            let dv = momentum / m;
+            for i in 0..body_state.len() {
+                body_state.v[i] -= dv;
+            }
+            state.bodies_edited = true;
+        }
+
+        state.reset = widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 4.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .label("Reset")
+            .label_font_size(12)
+            .set(ids.reset, ui)
+            .was_clicked();
+
+        state.clear_trails = widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 3.0)
+            .down(0.0)
+            .align_left_of(ids.pause_play_button)
+            .label("Clear\ntrails")
+            .label_font_size(12)
+            .set(ids.clear_trails, ui)
+            .was_clicked();
+
+        state.save_scenario = widget::Button::new()
+            .parent(area.id)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) * 2.0 / 3.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .label("Save as scenario")
+            .label_font_size(12)
+            .set(ids.save_scenario, ui)
+            .was_clicked()
+    }
+
+    let mut prev = match gen {
+        Some(area) => area.id,
+        None => ids.general
+    };
+    prev = lagrange_panel(ui, ids, masses, physics, state, body_state, bodies, prev);
+    prev = twin_panel(ui, ids, state, bodies, prev);
+    for i in 0..body_state.len() {
+        let elements = state.primaries[i].map(|p| Elements::relative(body_state, masses, physics, i, p));
+        prev = body_panel(i, bodies, elements, &mut masses[i], body_state, state, prev, ui, ids);
+        if let (Some(el), Some(p)) = (state.edited_orbit.take(), state.primaries[i]) {
+            let (x, v) = el.to_state(physics.g * (masses[i] + masses[p]));
+            // e.g. an eccentricity above 1 with a positive semi-major axis
+            if x.norm().is_finite() && v.norm().is_finite() {
+                body_state.x[i] = body_state.x[p] + x;
+                body_state.v[i] = body_state.v[p] + v;
+                state.bodies_edited = true;
+            }
+        }
+    }
+}
+
+/// Names and colors of the plotted quantities: the relative errors of the
+/// energy and the angular momentum, the distance of each pair of bodies and
+/// the speed of each body.
+fn plot_series(bodies: &[BodyData]) -> Vec<(String, conrod::Color)> {
+    let color = |c: Vector3<f32>| conrod::color::rgb(c.x, c.y, c.z);
+    let mut series = vec!(
+        ("energy error".to_string(), conrod::color::LIGHT_RED),
+        ("ang. momentum error".to_string(), conrod::color::LIGHT_GREEN)
+    );
+    for i in 0..bodies.len() {
+        for j in i + 1..bodies.len() {
+            let mix = (bodies[i].trail_color.coords + bodies[j].trail_color.coords) * 0.5;
+            series.push((format!("{}-{}", bodies[i].name, bodies[j].name), color(mix)));
+        }
+    }
+    for b in bodies {
+        series.push((format!("speed of {}", b.name), color(b.trail_color.coords)));
+    }
+    series
+}
+
+fn plot_sample(s: &State, m: &[f64], p: &Physics, reference: &Diagnostics) -> Vec<f64> {
+    let drift = Diagnostics::new(s, m, p).drift(reference);
+    let mut values = vec!(drift.energy, drift.angular_momentum);
+    for i in 0..s.len() {
+        for j in i + 1..s.len() {
+            values.push((s.x[i] - s.x[j]).norm());
+        }
+    }
+    for v in s.v.iter() {
+        values.push(v.norm());
+    }
+    values
+}
+
+fn plot_panel(
+    ui: &mut conrod::UiCell,
+    ids: &Ids,
+    state: &mut GuiState,
+    bodies: &[BodyData],
+    samples: &VecDeque<(f64, Vec<f64>)>
+) {
+    use conrod::{widget, Colorable, Labelable, Positionable, Sizeable, Widget};
+    const WIDTH: conrod::Scalar = 400.0;
+    const HEIGHT: conrod::Scalar = 160.0;
+    let series = plot_series(bodies);
+    if state.plot_series.len() != series.len() {
+        state.plot_series = (0..series.len()).map(|k| k == 0).collect();
+    }
+    let (a, e) = widget::CollapsibleArea::new(state.plot_open, "plot")
+        .top_left()
+        .label_font_size(12)
+        .w_h(WIDTH, 20.0)
+        .set(ids.plot, ui);
+    for e in e {
+        state.plot_open = e.is_open();
+    }
+    for area in a {
+        let rows = (series.len() + 1) / 2;
+        let canvas = widget::Canvas::new()
+            .h(HEIGHT + 50.0 + 20.0 * rows as f64 + 2.0 * MARGIN)
+            .pad(MARGIN);
+        area.set(canvas, ui);
+
+        // The y axis fits the selected series over the whole window.
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+        for (_, values) in samples.iter() {
+            for (v, &on) in values.iter().zip(state.plot_series.iter()) {
+                if on && v.is_finite() {
+                    min = min.min(*v);
+                    max = max.max(*v);
+                }
+            }
+        }
+        if min > max {
+            min = 0.0;
+            max = 1.0;
+        } else if min == max {
+            let pad = if min == 0.0 { 1.0 } else { min.abs() * 0.1 };
+            min -= pad;
+            max += pad;
+        }
+        let (start, end) = match (samples.front(), samples.back()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => (0.0, 0.0)
+        };
+
+        let width = area.width - 2.0 * MARGIN;
+        widget::Text::new(&format!("{:.3e}", max))
+            .font_size(12)
+            .parent(area.id)
+            .top_left()
+            .set(ids.plot_max, ui);
+        widget::Rectangle::outline([width, HEIGHT])
+            .color(conrod::color::rgba(1.0, 1.0, 1.0, 0.3))
+            .parent(area.id)
+            .align_left_of(ids.plot_max)
+            .down_from(ids.plot_max, 5.0)
+            .set(ids.plot_frame, ui);
+        widget::Text::new(&format!("{:.3e}", min))
+            .font_size(12)
+            .parent(area.id)
+            .align_left_of(ids.plot_frame)
+            .down_from(ids.plot_frame, 5.0)
+            .set(ids.plot_min, ui);
+        widget::Text::new(&format!("{} to {}", state.units.format_time(start), state.units.format_time(end)))
+            .font_size(12)
+            .parent(area.id)
+            .align_right_of(ids.plot_frame)
+            .down_from(ids.plot_frame, 5.0)
+            .set(ids.plot_time, ui);
+
+        if let (Some(rect), true) = (ui.rect_of(ids.plot_frame), samples.len() > 1) {
+            // Time runs backwards in reverse, which the axis follows.
+            let span = if end == start { 1.0 } else { end - start };
+            for k in 0..series.len() {
+                if !state.plot_series[k] {
+                    continue;
+                }
+                // Samples from before a merge have more values.
+                let points: Vec<[f64; 2]> = samples.iter()
+                    .filter_map(|(t, values)| values.get(k).filter(|v| v.is_finite()).map(|v| (t, v)))
+                    .map(|(t, v)| [
+                        rect.left() + (t - start) / span * rect.w(),
+                        rect.bottom() + (v - min) / (max - min) * rect.h()
+                    ])
+                    .collect();
+                widget::PointPath::new(points)
+                    .wh_of(ids.plot_frame)
+                    .middle_of(ids.plot_frame)
+                    .color(series[k].1)
+                    .graphics_for(ids.plot_frame)
+                    .set(ids.plot_lines[k], ui);
+            }
+        }
+
+        let half = width / 2.0;
+        for k in 0..series.len() {
+            let toggle = widget::Toggle::new(state.plot_series[k])
+                .parent(area.id)
+                .label(&series[k].0)
+                .label_color(series[k].1)
+                .label_font_size(12)
+                .w(half)
+                .h(20.0);
+            let toggle = match k {
+                0 => toggle.align_left_of(ids.plot_min).down_from(ids.plot_min, 5.0),
+                _ if k % 2 == 1 => toggle.right(0.0).y_relative(0.0),
+                _ => toggle.align_left_of(ids.plot_series[k - 2]).down_from(ids.plot_series[k - 2], 0.0)
+            };
+            for on in toggle.set(ids.plot_series[k], ui) {
+                state.plot_series[k] = on;
+            }
+        }
+    }
+}
+
+fn twin_panel(
+    ui: &mut conrod::UiCell,
+    ids: &Ids,
+    state: &mut GuiState,
+    bodies: &[BodyData],
+    previous: conrod::widget::Id
+) -> conrod::widget::Id {
+    use conrod::{widget, Borderable, Labelable, Positionable, Sizeable, Widget};
+    const WIDTH: conrod::Scalar = 200.0;
+    let (a, e) = widget::CollapsibleArea::new(state.twin_open, "twin")
+        .w_h(WIDTH, 20.0)
+        .down_from(previous, 0.0)
+        .set(ids.twin_panel, ui);
+    for e in e {
+        state.twin_open = e.is_open();
+    }
+    state.twin_changed = false;
+    for area in a {
+        let canvas = widget::Canvas::new()
+            .h(120.0)
+            .pad(MARGIN);
+        area.set(canvas, ui);
+        let half = (area.width - 2.0 * MARGIN) / 2.0;
+        let names: Vec<String> = bodies.iter().map(|b| format!("nudge {}", b.name)).collect();
+        for i in widget::DropDownList::new(&names, Some(state.twin_body.min(bodies.len() - 1)))
+            .parent(area.id)
+            .align_top()
+            .align_left()
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.twin_body, ui)
+        {
+            state.twin_body = i;
+            state.twin_changed = true;
+        }
+        for c in widget::DropDownList::new(&["x", "y", "z", "vx", "vy", "vz"], Some(state.twin_component))
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.twin_component, ui)
+        {
+            state.twin_component = c;
+            state.twin_changed = true;
+        }
+        for s in widget::Toggle::new(state.twin)
+            .parent(area.id)
+            .label("twin")
+            .align_left_of(ids.twin_body)
+            .down_from(ids.twin_body, 0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.twin, ui)
+        {
+            state.twin = s;
+        }
+        for size in widget::NumberDialer::new(state.twin_size, -15.0, -1.0, 0)
+            .parent(area.id)
+            .label("by 1e")
+            .border(0.0)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.twin_size, ui)
+        {
+            state.twin_size = size;
+            state.twin_changed = true;
+        }
+        let distance = match state.twin_distance {
+            Some(d) => format!("phase-space distance: {:.3e}", d),
+            None => String::new()
+        };
+        widget::Text::new(&distance)
+            .font_size(12)
+            .w(area.width - 2.0 * MARGIN)
+            .parent(area.id)
+            .align_left_of(ids.twin)
+            .down_from(ids.twin, 5.0)
+            .set(ids.twin_distance, ui);
+    }
+    match a {
+        Some(area) => area.id,
+        None => ids.twin_panel
+    }
+}
+
+const LAGRANGE_POINTS: [&str; 5] = ["at L1", "at L2", "at L3", "at L4", "at L5"];
+
+fn lagrange_panel(
+    ui: &mut conrod::UiCell,
+    ids: &Ids,
+    masses: &[f64],
+    physics: &Physics,
+    state: &mut GuiState,
+    body_state: &mut State,
+    bodies: &[BodyData],
+    previous: conrod::widget::Id
+) -> conrod::widget::Id {
+    use conrod::{widget, Borderable, Labelable, Positionable, Sizeable, Widget};
+    const WIDTH: conrod::Scalar = 200.0;
+    let (a, e) = widget::CollapsibleArea::new(state.lagrange_open, "Lagrange points")
+        .w_h(WIDTH, 20.0)
+        .down_from(previous, 0.0)
+        .set(ids.lagrange, ui);
+    for e in e {
+        state.lagrange_open = e.is_open();
+    }
+    for area in a {
+        let canvas = widget::Canvas::new()
+            .h(140.0)
+            .pad(MARGIN);
+        area.set(canvas, ui);
+        let names: Vec<&str> = bodies.iter().map(|b| b.name.as_str()).collect();
+        let half = (area.width - 2.0 * MARGIN) / 2.0;
+        for i in widget::DropDownList::new(&names, Some(state.lagrange_primary))
+            .parent(area.id)
+            .align_top()
+            .align_left()
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.lagrange_primary, ui)
+        {
+            state.lagrange_primary = i;
+        }
+        for i in widget::DropDownList::new(&names, Some(state.lagrange_secondary))
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.lagrange_secondary, ui)
+        {
+            state.lagrange_secondary = i;
+        }
+        let (primary, secondary) = (state.lagrange_primary, state.lagrange_secondary);
+        for s in widget::Toggle::new(state.show_lagrange)
+            .parent(area.id)
+            .label("show")
+            .align_left_of(ids.lagrange_primary)
+            .down_from(ids.lagrange_primary, 0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.show_lagrange, ui)
+        {
+            state.show_lagrange = s;
+        }
+        // The pair's frame: the primary fixed, the secondary kept on x.
+        if widget::Button::new()
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half)
+            .h(30.0)
+            .label("co-rotate")
+            .label_font_size(12)
+            .enabled(primary != secondary)
+            .set(ids.co_rotate, ui)
+            .was_clicked()
+        {
+            state.fix = FixState::Fix(primary, Some(secondary));
+        }
+        let placed: Vec<String> = bodies.iter().map(|b| format!("place {}", b.name)).collect();
+        for i in widget::DropDownList::new(&placed, Some(state.placed_body))
+            .parent(area.id)
+            .align_left_of(ids.show_lagrange)
+            .down_from(ids.show_lagrange, 0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.placed_body, ui)
+        {
+            state.placed_body = i;
+        }
+        for k in widget::DropDownList::new(&LAGRANGE_POINTS, Some(state.lagrange_point))
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half / 2.0)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.lagrange_point, ui)
+        {
+            state.lagrange_point = k;
+        }
+        let i = state.placed_body;
+        if widget::Button::new()
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half / 2.0)
+            .h(30.0)
+            .label("Place")
+            .label_font_size(12)
+            .enabled(state.editable() && i != primary && i != secondary && i < body_state.len())
+            .set(ids.place, ui)
+            .was_clicked()
+        {
+            if let Some(l) = LagrangePoints::new(body_state, masses, physics, primary, secondary) {
+                let (x, v) = l.state(state.lagrange_point);
+                body_state.x[i] = x;
+                body_state.v[i] = v;
+                state.bodies_edited = true;
+            }
+        }
+        for n in widget::NumberDialer::new(state.particle_count as f64, 0.0, 9999.0, 0)
+            .parent(area.id)
+            .label("particles")
+            .border(0.0)
+            .align_left_of(ids.placed_body)
+            .down_from(ids.placed_body, 0.0)
+            .w(half)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.particle_count, ui)
+        {
+            state.particle_count = n as usize;
+        }
+        let point = LAGRANGE_POINTS[state.lagrange_point];
+        if widget::Button::new()
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half / 2.0)
+            .h(30.0)
+            .label(point)
+            .label_font_size(12)
+            .enabled(primary != secondary)
+            .set(ids.seed_lagrange, ui)
+            .was_clicked()
+        {
+            state.seed_particles = Some(Seeding::Lagrange);
+        }
+        if widget::Button::new()
+            .parent(area.id)
+            .right(0.0)
+            .y_relative(0.0)
+            .w(half / 2.0)
+            .h(30.0)
+            .label("disk")
+            .label_font_size(12)
+            .enabled(primary != secondary)
+            .set(ids.seed_disk, ui)
+            .was_clicked()
+        {
+            state.seed_particles = Some(Seeding::Disk);
+        }
+    }
+    match a {
+        Some(area) => area.id,
+        None => ids.lagrange
+    }
+}
+
+/// Power of ten in which a dialer shows values of about the size of
+/// `reference`, so that a few decimals are enough to edit them. Values in
+/// simulation units are shown as they are.
+fn dialer_scale(units: UnitSystem, reference: f64) -> f64 {
+    if units == UnitSystem::Simulation || reference == 0.0 {
+        1.0
+    } else {
+        10.0f64.powf(reference.abs().log10().floor())
+    }
+}
+
+/// Dialer label such as "mass [1e24 kg]".
+fn unit_label(name: &str, scale: f64, unit: &str) -> String {
+    let mut parts = Vec::new();
+    if scale != 1.0 {
+        parts.push(format!("1e{}", scale.log10().round()));
+    }
+    if !unit.is_empty() {
+        parts.push(unit.to_string());
+    }
+    if parts.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} [{}]", name, parts.join(" "))
+    }
+}
+
+fn default_primaries(s: &State, m: &[f64]) -> Vec<Option<usize>> {
+    (0..s.len()).map(|i| orbit::dominant_body(s, m, i)).collect()
+}
+
+/// The elements that follow from the ones in the editors.
+fn orbit_text(el: &Elements, units: UnitSystem) -> String {
+    let length = |x: f64| units::quantity(x, units.length());
+    let period = if el.period.is_finite() { units.format_time(el.period) } else { "unbound".to_string() };
+    format!("period: {}\nperiapsis: {}\napoapsis: {}", period, length(el.periapsis), length(el.apoapsis))
+}
+
+fn body_panel(
+    i: usize,
+    bodies: &[BodyData],
+    elements: Option<Elements>,
+    mass: &mut f64,
+    body_state: &mut State,
+    state: &mut GuiState,
+    previous: conrod::widget::Id,
+    ui: &mut conrod::UiCell,
+    ids: &Ids
+) -> conrod::widget::Id {
+    use conrod::{widget, Borderable, Labelable, Positionable, Sizeable, Widget};
+    const WIDTH: conrod::Scalar = 200.0;
+    let body = &bodies[i];
+    let (a, e) = widget::CollapsibleArea::new(state.body_panel_open[i], &body.name)
+        .w_h(WIDTH, 20.0)
+        .down_from(previous, 0.0)
+        .set(ids.body_panel[i], ui);
+    for e in e {
+        state.body_panel_open[i] = e.is_open();
+    }
+    for area in a {
+        let canvas = widget::Canvas::new()
+            .h(370.0)
+            .pad(MARGIN);
+        area.set(canvas, ui);
+        let units = state.units;
+        let editable = state.editable();
+        let m_scale = dialer_scale(units, body.mass);
+        for m in widget::NumberDialer::new(*mass / m_scale, 0.0, 9999.0, if m_scale == 1.0 { 1 } else { 3 })
+            .parent(area.id)
+            .label(&unit_label("mass", m_scale, units.mass()))
+            .enabled(editable)
+            .border(0.0)
+            .align_top()
+            .align_middle_x()
+            .w(area.width - 2.0 * MARGIN)
+            .h(30.0)
+            .label_font_size(12)
+            .set(ids.mass[i], ui)
+        {
+            *mass = m * m_scale;
+            state.bodies_edited = true;
+        }
+        let v_scale = dialer_scale(units, body.v.norm());
+        let v = body_state.v[i].norm() / v_scale;
+        // A body at rest has no direction to scale its velocity along; its
+        // velocity is set with the component editors below.
+        let direction = body_state.v[i].try_normalize(0.0);
+        for nv in widget::NumberDialer::new(v, 1.0, 9999.0, if v_scale == 1.0 { 1 } else { 3 })
+            .parent(area.id)
+            .label(&unit_label("velocity", v_scale, units.velocity()))
+            .border(0.0)
+            .align_left()
+            .down(0.0)
+            .h(30.0)
+            .w(area.width - 2.0 * MARGIN)
+            .label_font_size(12)
+            .enabled(editable && direction.is_some())
+            .set(ids.velocity[i], ui)
+        {
+            body_state.v[i] = direction.unwrap() * nv * v_scale;
+            state.bodies_edited = true;
+        }
+        // Shared by all bodies so that their components compare directly.
+        let x_scale = dialer_scale(units, bodies.iter().map(|b| b.x.norm()).fold(0.0, f64::max));
+        let v_scale = dialer_scale(units, bodies.iter().map(|b| b.v.norm()).fold(0.0, f64::max));
+        let precision = if units == UnitSystem::Simulation { 1 } else { 2 };
+        for k in 0..6 {
+            let (vector, c, scale) = if k < 3 {
+                (&mut body_state.x[i], k, x_scale)
+            } else {
+                (&mut body_state.v[i], k - 3, v_scale)
+            };
+            let dialer = widget::NumberDialer::new(vector[c] / scale, -999.0, 999.0, precision)
+                .parent(area.id)
+                .label(["x", "y", "z", "vx", "vy", "vz"][k])
+                .border(0.0)
+                .h(30.0)
+                .w((area.width - 2.0 * MARGIN) / 3.0)
+                .label_font_size(10)
+                .enabled(editable);
+            // Three editors per row.
+            let dialer = match k {
+                0 | 3 => dialer.align_left().down(0.0),
+                _ => dialer.right(0.0).y_relative(0.0)
+            };
+            for value in dialer.set(ids.components[6 * i + k], ui) {
+                vector[c] = value * scale;
+                state.bodies_edited = true;
+            }
+        }
+        widget::Text::new(&format!("{}, {}", unit_label("position", x_scale, units.length()),
+                                   unit_label("velocity", v_scale, units.velocity())))
+            .font_size(10)
+            .w(area.width - 2.0 * MARGIN)
+            .parent(area.id)
+            .align_left()
+            .down(0.0)
+            .set(ids.component_units[i], ui);
+        for s in widget::Toggle::new(state.follow == Some(i))
+            .parent(area.id)
+            .label("follow")
+            .align_left()
+            .down(0.0)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 3.0)
+            .label_font_size(12)
+            .set(ids.follow[i], ui)
+        {
+            state.follow = if s { Some(i) } else { None };
+        }
+        for s in widget::Toggle::new(state.fix.fix_center() == Some(i))
+            .parent(area.id)
+            .label("fix")
+            .right(0.0)
+            .y_relative(0.0)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 3.0)
+            .label_font_size(12)
+            .set(ids.fix[i], ui)
+        {
+            state.fix = if s { FixState::Fix(i, None) } else { FixState::None };
+        }
+        for s in widget::Toggle::new(state.fix.fix_rot() == Some(i))
+            .parent(area.id)
+            .label("fix rot")
+            .enabled(state.fix.fix_center() != None && state.fix.fix_center() != Some(i))
+            .right(0.0)
+            .y_relative(0.0)
+            .h(30.0)
+            .w((area.width - 2.0 * MARGIN) / 3.0)
+            .label_font_size(12)
+            .set(ids.fix_rot[i], ui)
+        {
+            state.fix = if s {
+                FixState::Fix(state.fix.fix_center().unwrap(), Some(i))
+            } else {
+                FixState::Fix(state.fix.fix_center().unwrap(), None)
+            };
+        }
+        let primaries: Vec<String> = bodies.iter().map(|b| format!("around {}", b.name)).collect();
+        for p in widget::DropDownList::new(&primaries, state.primaries[i])
+            .parent(area.id)
+            .label("primary")
+            .align_left_of(ids.follow[i])
+            .down_from(ids.follow[i], 0.0)
+            .h(30.0)
+            .w(area.width - 2.0 * MARGIN)
+            .label_font_size(12)
+            .set(ids.primary[i], ui)
+        {
+            state.primaries[i] = if p == i { None } else { Some(p) };
+        }
+        let el = match elements {
+            Some(el) => el,
+            None => {
+                widget::Text::new("no primary")
+                    .font_size(12)
+                    .w(area.width - 2.0 * MARGIN)
+                    .parent(area.id)
+                    .set(ids.orbit[i], ui);
+                return area.id;
+            }
+        };
+        let a_scale = dialer_scale(units, el.semi_major_axis);
+        let mut values = [
+            el.semi_major_axis / a_scale,
+            el.eccentricity,
+            el.inclination.to_degrees(),
+            el.node.to_degrees(),
+            el.periapsis_argument.to_degrees(),
+            el.true_anomaly.to_degrees()
+        ];
+        let dialers = [
+            (unit_label("a", a_scale, units.length()), -9999.0, 9999.0, 2),
+            ("e".to_string(), 0.0, 99.0, 3),
+            ("i".to_string(), 0.0, 180.0, 1),
+            ("node".to_string(), 0.0, 360.0, 1),
+            ("arg".to_string(), 0.0, 360.0, 1),
+            ("anomaly".to_string(), 0.0, 360.0, 1)
+        ];
+        let mut changed = false;
+        for (k, (label, min, max, precision)) in dialers.iter().enumerate() {
+            let id = ids.elements[6 * i + k];
+            let dialer = widget::NumberDialer::new(values[k], *min, *max, *precision)
+                .parent(area.id)
+                .label(label)
+                .border(0.0)
+                .h(30.0)
+                .w((area.width - 2.0 * MARGIN) / 2.0)
+                .label_font_size(12)
+                .enabled(editable);
+            // Two editors per row.
+            let dialer = match k {
+                0 => dialer.align_left_of(ids.primary[i]).down_from(ids.primary[i], 0.0),
+                _ if k % 2 == 0 => dialer.align_left_of(ids.elements[6 * i + k - 2]).down(0.0),
+                _ => dialer.right(0.0).y_relative(0.0)
+            };
+            for v in dialer.set(id, ui) {
+                values[k] = v;
+                changed = true;
+            }
+        }
+        if changed {
+            state.edited_orbit = Some(Elements {
+                semi_major_axis: values[0] * a_scale,
+                eccentricity: values[1],
+                inclination: values[2].to_radians(),
+                node: values[3].to_radians(),
+                periapsis_argument: values[4].to_radians(),
+                true_anomaly: values[5].to_radians(),
+                ..el
+            });
+        }
+        widget::Text::new(&orbit_text(&el, units))
+            .font_size(12)
+            .w(area.width - 2.0 * MARGIN)
+            .parent(area.id)
+            .align_left_of(ids.primary[i])
+            .down(0.0)
+            .set(ids.orbit[i], ui);
+    }
+    match a {
+        Some(area) => area.id,
+        None => ids.body_panel[i]
+    }
+}